@@ -2,8 +2,12 @@ use super::manager_commands::*;
 use anyhow::Result;
 use structopt::StructOpt;
 // use crate::server::server_main;
-use reqwest::blocking::Client;
-use crate::server::main_thread;
+use crate::server::{main_thread, TokenFile, DEFAULT_ADDRESS};
+#[cfg(unix)]
+use crate::server::{default_socket_path, post_over_socket, socket_in_use};
+use std::path::PathBuf;
+use crate::scheduler::{Response, ResponseBody, API_VERSION};
+use super::render::render;
 use serde::{Serialize, Deserialize};
 
 
@@ -15,29 +19,159 @@ use serde::{Serialize, Deserialize};
 pub struct Manager {
     #[structopt(subcommand)]
     pub subcommands: ManagerCommands,
+    /// Print the json response of the server as it is
+    #[structopt(long, global = true)]
+    #[serde(skip)]
+    pub json: bool,
+    /// The server to send the command to, like 127.0.0.1:7878, [::1]:7878 or http://build-box:7878
+    /// [default: the unix socket if it exists, else 127.0.0.1:7878]
+    #[structopt(long, global = true, env = "MANAGER_URL")]
+    #[serde(skip)]
+    pub server: Option<String>,
+    /// The unix socket of the server, start listens on it and the cli uses it if no --server is given
+    #[structopt(long, global = true, env = "MANAGER_SOCKET")]
+    #[serde(skip)]
+    pub socket: Option<PathBuf>,
+    #[structopt(flatten)]
+    #[serde(skip)]
+    pub tls: ClientTlsOptions,
+    /// The file of the API tokens, the server generates it at its first start and the cli reads it
+    #[structopt(long, global = true, env = "MANAGER_TOKEN_FILE")]
+    #[serde(skip)]
+    pub token_file: Option<PathBuf>,
+    /// The bearer token to send instead of the one of the token file
+    #[structopt(long, global = true, env = "MANAGER_TOKEN", hide_env_values = true)]
+    #[serde(skip)]
+    pub token: Option<String>,
 }
 
 impl Manager {
-    pub fn handle(&self) -> Result<String> {
+    fn token_file(&self) -> PathBuf {
+        self.token_file.clone().unwrap_or_else(TokenFile::default_path)
+    }
+
+    // the token given to the cli, else the one of the token file
+    // without a token the request is sent anyway and the server refuses it
+    fn token(&self) -> Result<Option<String>> {
+        if let Some(token) = &self.token {
+            return Ok(Some(token.clone()));
+        }
+
+        let tokens = TokenFile::load(&self.token_file())?;
+        Ok(tokens.and_then(|tokens| tokens.client_token().map(String::from)))
+    }
+
+    #[cfg(unix)]
+    fn socket_path(&self) -> Option<PathBuf> {
+        Some(self.socket.clone().unwrap_or_else(default_socket_path))
+    }
+
+    #[cfg(not(unix))]
+    fn socket_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    // the socket start listens on, the given one or the default one if no other manager listens on it
+    #[cfg(unix)]
+    fn listen_socket(&self) -> Option<PathBuf> {
+        if self.socket.is_some() {
+            return self.socket.clone();
+        }
+
+        let path = default_socket_path();
+        if socket_in_use(&path) {
+            eprintln!("Warning: another manager listens on {}, this one doesn't use the socket", path.display());
+            return None;
+        }
+        Some(path)
+    }
+
+    #[cfg(not(unix))]
+    fn listen_socket(&self) -> Option<PathBuf> {
+        self.socket_path()
+    }
+
+    // the server is an address or a url, an address is reached with http, or https with the tls options
+    fn server_url(&self, path: &str) -> String {
+        let server = self.server.as_deref().unwrap_or(DEFAULT_ADDRESS).trim_end_matches('/');
+        if server.contains("://") {
+            format!("{}{}", server, path)
+        } else if self.tls.enabled() {
+            format!("https://{}{}", server, path)
+        } else {
+            format!("http://{}{}", server, path)
+        }
+    }
+
+    // the request goes to the unix socket if there is one and no server is given, else to the server with a token
+    async fn post(&self, path: &str, body: String) -> Result<(reqwest::StatusCode, String)> {
+        #[cfg(unix)]
+        if let Some(socket) = self.socket_path().filter(|socket| self.server.is_none() && socket.exists()) {
+            return post_over_socket(&socket, path, body).await;
+        }
+
+        let mut request = self
+            .tls
+            .client()?
+            .post(self.server_url(path))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body);
+        if let Some(token) = self.token()? {
+            request = request.bearer_auth(token);
+        }
+        let resp = request.send().await?;
+
+        Ok((resp.status(), resp.text().await?))
+    }
+
+    pub async fn handle(&self) -> Result<String> {
         match self.subcommands.clone() {
             ManagerCommands::Start {
                 active_downloads,
                 download_path,
+                listen,
+                no_tcp,
+                retention,
+                client,
+                tls,
+                server,
             } => {
-                main_thread(active_downloads, download_path);
+                let listen = if no_tcp { vec![] } else { listen };
+                main_thread(
+                    active_downloads,
+                    download_path,
+                    listen,
+                    self.listen_socket(),
+                    self.token_file(),
+                    retention,
+                    client,
+                    tls,
+                    server,
+                )
+                .await?;
                 Ok(String::from("Good Bye!"))
             }
-            ManagerCommands::Cancel { .. } => {
-                Ok(String::from("cancel command [not working yet]"))
-            }
             _ => {
-                let client = Client::new();
-                let resp = client.post("http://127.0.0.1:7878/command").json(&self.clone()).send()?;
-
-                let resp = resp.text()?;
+                let (status, resp) = self.post("/command", serde_json::to_string(self)?).await?;
+                if self.json {
+                    return Ok(resp);
+                }
 
-                Ok(resp)
+                // the errors are answered with their status code and the same json
+                let response: Response = serde_json::from_str(&resp)
+                    .map_err(|_| anyhow::anyhow!("The server answered {}: {}", status, resp))?;
+                if response.api_version != API_VERSION {
+                    return Err(anyhow::anyhow!(
+                        "The server answers with the api version {}, this cli understands {}",
+                        response.api_version,
+                        API_VERSION
+                    ));
+                }
 
+                match response.body {
+                    ResponseBody::Error(e) => Err(anyhow::anyhow!("{} ({})", e.message, e.code.name())),
+                    body => Ok(render(&body)),
+                }
             }
         }
         
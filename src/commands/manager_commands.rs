@@ -1,27 +1,125 @@
 
 use structopt::StructOpt;
 use serde::{Serialize, Deserialize};
+use crate::server::DEFAULT_ADDRESS;
+use std::path::PathBuf;
 
 #[derive(StructOpt, Debug, Clone, Serialize, Deserialize)]
 pub enum ManagerCommands {
     /// Add a url with optional name to be downloaded, name will be file name by default
     Add {
+        #[structopt(flatten)]
+        #[serde(flatten)]
+        options: AddOptions,
+    },
+    /// Move a failed job back to the waiting list with its original options, its partial file is deleted first
+    Retry {
+        #[structopt(short, long)]
+        id: usize,
+    },
+    /// Move all failed jobs back to the waiting list
+    RetryAllFailed,
+    /// Download a completed job again as a new job
+    Redownload {
+        #[structopt(short, long)]
+        id: usize,
+    },
+    /// Add a new job with the options of an old one, the given options replace the old ones
+    Clone {
+        #[structopt(short, long)]
+        id: usize,
+        #[structopt(flatten)]
+        #[serde(flatten)]
+        overrides: CloneOverrides,
+    },
+    /// Drop a waiting or finished job, and optionally its downloaded file
+    Remove {
+        #[structopt(short, long)]
+        id: usize,
+        #[structopt(long)]
+        #[serde(default)]
+        delete_file: bool,
+    },
+    /// Drop the finished jobs that match all the given filters, and optionally their downloaded files
+    Purge {
+        /// Done, NotModified, Failed or Blocked
+        #[structopt(short, long)]
+        state: Option<String>,
+        /// Finished before this age, like 30d, 12h, 45m or 10s
+        #[structopt(short, long)]
+        older_than: Option<String>,
+        #[structopt(short, long)]
+        queue: Option<String>,
+        #[structopt(long)]
+        #[serde(default)]
+        delete_files: bool,
+    },
+    /// Change the retention policy the scheduler applies to the finished jobs
+    Retention {
+        #[structopt(flatten)]
+        #[serde(flatten)]
+        policy: RetentionPolicy,
+    },
+    /// Set the command that runs once all jobs of a group are done successfully
+    Group {
+        #[structopt(short, long)]
+        name: String,
+        #[structopt(short="c", long)]
+        hook: String,
+    },
+    /// Download a url again every time the cron expression fires, the name template accepts {name}, {date} and {time}
+    Schedule {
         #[structopt(short, long)]
         url: String,
-        #[structopt(short="f", long)]
-        custom_name: Option<String>,
+        #[structopt(short, long)]
+        cron: String,
         #[structopt(short="p", long)]
         custom_download_path: Option<String>,
+        #[structopt(short="f", long)]
+        name_template: Option<String>,
+    },
+    /// Remove a schedule, the downloads it already added stay
+    Unschedule {
+        #[structopt(short, long)]
+        id: usize,
     },
-    /// List [all, active, done] jobs
+    /// List [all, active, done, schedules, groups, queues] jobs
     List {
+        /// List only the jobs of this queue
+        #[structopt(short, long)]
+        queue: Option<String>,
+        /// Sort the jobs by id, created, started, finished, duration, throughput or wait
+        #[structopt(short, long)]
+        sort: Option<String>,
+        /// Sort from the largest value to the smallest
+        #[structopt(long)]
+        #[serde(default)]
+        descending: bool,
         #[structopt(subcommand)]
         subcommands: ListCommands,
     },
-    /// Cancel an active job
+    /// Create or delete a named queue
+    Queue {
+        #[structopt(subcommand)]
+        subcommands: QueueCommands,
+    },
+    /// Stop a waiting, paused or active job, it stays in the list as failed and can be retried, and optionally delete its file
     Cancel {
-        #[structopt(short)]
-        filename: String,
+        #[structopt(short, long)]
+        id: usize,
+        #[structopt(long)]
+        #[serde(default)]
+        delete_file: bool,
+    },
+    /// Stop the manager, the active downloads finish or are paused and the jobs are saved for the next start
+    Shutdown {
+        /// The seconds to wait for the active downloads before pausing them, 30 by default
+        #[structopt(short, long)]
+        timeout: Option<u64>,
+        /// Pause the active downloads without waiting
+        #[structopt(long)]
+        #[serde(default)]
+        pause: bool,
     },
     /// Prints info for a job
     Info {
@@ -34,6 +132,26 @@ pub enum ManagerCommands {
         active_downloads: u16,
         #[structopt(short="p", long)]
         download_path: String,
+        /// An address the server listens on, like 127.0.0.1:7878 or [::1]:7878, it can be given more than once
+        #[structopt(short, long, default_value = DEFAULT_ADDRESS, number_of_values = 1)]
+        #[serde(default)]
+        listen: Vec<String>,
+        /// Listen only on the unix socket, without a tcp address
+        #[structopt(long)]
+        #[serde(default)]
+        no_tcp: bool,
+        #[structopt(flatten)]
+        #[serde(flatten)]
+        retention: RetentionPolicy,
+        #[structopt(flatten)]
+        #[serde(flatten)]
+        client: ClientOptions,
+        #[structopt(flatten)]
+        #[serde(flatten)]
+        tls: TlsOptions,
+        #[structopt(flatten)]
+        #[serde(flatten)]
+        server: ServerOptions,
     }
 }
 
@@ -42,4 +160,171 @@ pub enum ListCommands {
     All,
     Active,
     Done,
-}
\ No newline at end of file
+    Schedules,
+    Groups,
+    Queues,
+}
+
+#[derive(StructOpt, Debug, Clone, Serialize, Deserialize)]
+pub enum QueueCommands {
+    /// Create a queue with its own number of active downloads, download path and speed limit
+    Create {
+        #[structopt(short, long)]
+        name: String,
+        #[structopt(short, long)]
+        active_downloads: u16,
+        #[structopt(short="p", long)]
+        download_path: Option<String>,
+        /// The speed limit of the whole queue in bytes per second
+        #[structopt(short, long)]
+        speed_limit: Option<u64>,
+    },
+    /// Delete a queue that has no waiting or active jobs
+    Delete {
+        #[structopt(short, long)]
+        name: String,
+    },
+}
+
+// the options of the Add command
+#[derive(StructOpt, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AddOptions {
+    #[structopt(short, long)]
+    pub url: String,
+    #[structopt(short="f", long)]
+    pub custom_name: Option<String>,
+    #[structopt(short="p", long)]
+    pub custom_download_path: Option<String>,
+    /// Skip the download if the server reports the file didn't change since the last download of the url
+    #[structopt(long)]
+    #[serde(default)]
+    pub only_if_changed: bool,
+    /// Start only after the jobs with these ids are done successfully
+    #[structopt(short, long)]
+    #[serde(default)]
+    pub after: Vec<usize>,
+    /// Add the job to a named group
+    #[structopt(short, long)]
+    pub group: Option<String>,
+    /// Add the job to a named queue instead of the default one
+    #[structopt(short, long)]
+    pub queue: Option<String>,
+    /// Download through this proxy, like http://proxy:8080 or socks5://proxy:1080
+    #[structopt(long)]
+    pub proxy: Option<String>,
+    /// Accept invalid tls certificates of the server
+    #[structopt(long)]
+    #[serde(default)]
+    pub insecure: bool,
+    /// A header to send with the requests, like "Authorization: Bearer <token>"
+    #[structopt(long = "header")]
+    #[serde(default)]
+    pub headers: Vec<String>,
+}
+
+// the options of the Clone command that replace the options of the cloned job
+#[derive(StructOpt, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CloneOverrides {
+    #[structopt(short, long)]
+    pub url: Option<String>,
+    #[structopt(short="f", long)]
+    pub custom_name: Option<String>,
+    #[structopt(short="p", long)]
+    pub custom_download_path: Option<String>,
+    #[structopt(short, long)]
+    pub group: Option<String>,
+    #[structopt(short, long)]
+    pub queue: Option<String>,
+    /// Download the copy through this proxy instead of the one of the job
+    #[structopt(long)]
+    pub proxy: Option<String>,
+    /// Accept invalid tls certificates of the server, even if the job doesn't
+    #[structopt(long)]
+    #[serde(default)]
+    pub insecure: bool,
+    /// The headers of the copy, they replace the ones of the job
+    #[structopt(long = "header")]
+    #[serde(default)]
+    pub headers: Vec<String>,
+}
+
+// how many finished jobs the scheduler keeps, the rest are dropped from the history (not from the disk)
+#[derive(StructOpt, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Keep only the last N finished jobs
+    #[structopt(long)]
+    pub keep_last: Option<usize>,
+    /// Keep only the jobs finished in the last N days
+    #[structopt(long)]
+    pub keep_days: Option<u64>,
+}
+
+// the settings of the http clients shared by all downloads
+#[derive(StructOpt, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClientOptions {
+    /// The maximum idle connections kept per host
+    #[structopt(long)]
+    pub pool_max_idle_per_host: Option<usize>,
+    /// The seconds an idle connection is kept
+    #[structopt(long)]
+    pub pool_idle_timeout: Option<u64>,
+    #[structopt(long)]
+    pub user_agent: Option<String>,
+    /// Use http2 with the servers that support it
+    #[structopt(long)]
+    #[serde(default)]
+    pub http2: bool,
+    /// Use http2 without negotiating it, for servers that are known to support it
+    #[structopt(long)]
+    #[serde(default)]
+    pub http2_prior_knowledge: bool,
+    /// The seconds to connect to a server before the download fails [default: 30]
+    #[structopt(long)]
+    pub connect_timeout: Option<u64>,
+    /// The seconds a download waits for the answer of the server or the next part of the body before it fails [default: 60]
+    #[structopt(long)]
+    pub stall_timeout: Option<u64>,
+}
+
+// what the control server serves, how much it reads from a client and how long it waits for it
+#[derive(StructOpt, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerOptions {
+    /// Serve the aria2 compatible JSON-RPC interface on /jsonrpc
+    #[structopt(long)]
+    #[serde(default)]
+    pub rpc: bool,
+    /// The largest request body the server accepts, in bytes [default: 65536]
+    #[structopt(long)]
+    pub max_body_size: Option<usize>,
+    /// The seconds a client has to send the headers of its request, and then its body [default: 10]
+    #[structopt(long)]
+    pub read_timeout: Option<u64>,
+}
+
+// the certificate of the control server, with a client ca the clients need a certificate too
+#[derive(StructOpt, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TlsOptions {
+    /// The pem certificate (chain) of the server, the tcp addresses serve https with it
+    #[structopt(long)]
+    pub tls_cert: Option<PathBuf>,
+    /// The pem private key of the certificate
+    #[structopt(long)]
+    pub tls_key: Option<PathBuf>,
+    /// Accept only the clients with a certificate signed by a ca of this pem file (mutual tls)
+    #[structopt(long)]
+    pub tls_client_ca: Option<PathBuf>,
+}
+
+// how the cli checks the server and shows itself to it, an address without a scheme uses https with them
+#[derive(StructOpt, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClientTlsOptions {
+    /// The pem ca certificate that signed the certificate of the server
+    #[structopt(long, global = true, env = "MANAGER_CA_CERT")]
+    pub ca_cert: Option<PathBuf>,
+    /// The pem client certificate for a server with mutual tls
+    #[structopt(long, global = true, env = "MANAGER_CLIENT_CERT")]
+    pub client_cert: Option<PathBuf>,
+    /// The pem private key of the client certificate
+    #[structopt(long, global = true, env = "MANAGER_CLIENT_KEY")]
+    pub client_key: Option<PathBuf>,
+}
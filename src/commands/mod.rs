@@ -1,5 +1,6 @@
 mod manager_commands;
 mod manager;
+mod render;
 
 pub use manager::*;
 pub use manager_commands::*;
\ No newline at end of file
@@ -0,0 +1,199 @@
+use crate::scheduler::{
+    DownloadError, GroupView, JobCounts, JobView, QueueView, ResponseBody, ScheduleView, State,
+};
+
+/*
+ * The render functions turn the body of a response into the text the cli prints
+ *
+ * - The server answers json only, the text is built here from the same views the other tools read
+ * - A list of jobs is one line per job followed by the counts of its states
+ */
+
+pub fn render(body: &ResponseBody) -> String {
+    match body {
+        ResponseBody::Message { message } => message.clone(),
+        ResponseBody::Added { id } => format!("File added with id {}", id),
+        ResponseBody::Count { message, .. } => message.clone(),
+        ResponseBody::Job { job } => render_info(job),
+        ResponseBody::Jobs { jobs, counts } => {
+            let mut ret = render_jobs(jobs);
+            ret.push_str(render_counts(counts).as_str());
+            ret
+        }
+        ResponseBody::Queues { queues } => render_queues(queues),
+        ResponseBody::Groups { groups } => render_groups(groups),
+        ResponseBody::Schedules { schedules } => render_schedules(schedules),
+        ResponseBody::Error(e) => format!("Error: {}", e),
+    }
+}
+
+// [id] name  ( downloaded / total )  State (error kind)
+fn render_job(job: &JobView) -> String {
+    let mut inf = String::new();
+    inf.push_str(format!("[{}] ", job.id).as_str());
+    inf.push_str(job.name.as_str());
+    inf.push_str(format!("  ( {} / ", job.downloaded_size).as_str());
+    inf.push_str(render_size(job.total_size).as_str());
+    inf.push_str(")  ");
+    inf.push_str(job.state.to_string().as_str());
+    if let Some(err) = &job.error {
+        inf.push_str(format!(" ({})", err.kind()).as_str());
+    }
+    inf.push_str("\r\n");
+    inf
+}
+
+fn render_jobs(jobs: &[JobView]) -> String {
+    jobs.iter().map(render_job).collect()
+}
+
+// only the states that have jobs are shown
+fn render_counts(counts: &JobCounts) -> String {
+    let states = [
+        (State::Waiting, counts.waiting),
+        (State::Active, counts.active),
+        (State::Paused, counts.paused),
+        (State::Done, counts.done),
+        (State::NotModified, counts.not_modified),
+        (State::Failed, counts.failed),
+        (State::Blocked, counts.blocked),
+    ];
+    let mut ret = format!("{} jobs", counts.total);
+    for (state, count) in states {
+        if count > 0 {
+            ret.push_str(format!("  {}: {}", state, count).as_str());
+        }
+    }
+    ret.push_str("\r\n");
+    ret
+}
+
+// a single job with its error, its options and its timing
+fn render_info(job: &JobView) -> String {
+    let mut inf = render_job(job);
+    if let Some(err) = &job.error {
+        inf.push_str(format!("error: {}\r\n", err).as_str());
+        inf.push_str(format!("error kind: {}\r\n", err.kind()).as_str());
+        if let DownloadError::HttpStatus { status, .. } = err {
+            inf.push_str(format!("http status: {}\r\n", status).as_str());
+        }
+    }
+    if !job.after.is_empty() {
+        let after: Vec<String> = job.after.iter().map(|id| id.to_string()).collect();
+        inf.push_str(format!("after: {}\r\n", after.join(", ")).as_str());
+    }
+    if let Some(group) = &job.group {
+        inf.push_str(format!("group: {}\r\n", group).as_str());
+    }
+    if let Some(final_size) = job.final_size {
+        inf.push_str(format!("size on disk: {}\r\n", final_size).as_str());
+    }
+    if let Some(etag) = &job.validators.etag {
+        inf.push_str(format!("etag: {}\r\n", etag).as_str());
+    }
+    if let Some(last_modified) = &job.validators.last_modified {
+        inf.push_str(format!("last modified: {}\r\n", last_modified).as_str());
+    }
+    inf.push_str(render_timing(job).as_str());
+    inf
+}
+
+// the timestamps of a job, its time in the queue and every attempt with its duration and speed
+fn render_timing(job: &JobView) -> String {
+    let mut ret = String::new();
+    ret.push_str(format!("created at: {}\r\n", job.created_at.to_rfc3339()).as_str());
+    if let Some(started_at) = job.started_at {
+        ret.push_str(format!("started at: {}\r\n", started_at.to_rfc3339()).as_str());
+    }
+    if let Some(finished_at) = job.finished_at {
+        ret.push_str(format!("finished at: {}\r\n", finished_at.to_rfc3339()).as_str());
+    }
+    if job.state != State::Blocked {
+        let queue_wait = chrono::Duration::milliseconds(job.queue_wait_ms);
+        ret.push_str(format!("queue wait: {}\r\n", render_duration(queue_wait)).as_str());
+    }
+    if let Some(throughput) = job.throughput {
+        ret.push_str(format!("throughput: {} B/s\r\n", throughput.round()).as_str());
+    }
+    for (index, attempt) in job.attempts.iter().enumerate() {
+        ret.push_str(format!("attempt {}: waited {}", index + 1, render_duration(attempt.wait())).as_str());
+        if let Some(duration) = attempt.duration() {
+            ret.push_str(format!("  ran {}", render_duration(duration)).as_str());
+        }
+        ret.push_str(format!("  {} bytes", attempt.downloaded).as_str());
+        if let Some(throughput) = attempt.throughput() {
+            ret.push_str(format!("  {} B/s", throughput.round()).as_str());
+        }
+        ret.push_str(format!("  {}\r\n", attempt.state).as_str());
+    }
+
+    ret
+}
+
+fn render_duration(duration: chrono::Duration) -> String {
+    format!("{:.3}s", duration.num_milliseconds() as f64 / 1000.0)
+}
+
+// the total size is unknown until the head request of the download returns
+fn render_size(size: Option<u64>) -> String {
+    match size {
+        Some(size) => size.to_string(),
+        None => String::from("_"),
+    }
+}
+
+// every queue with its limits followed by its download objects
+fn render_queues(queues: &[QueueView]) -> String {
+    let mut ret = String::new();
+    for q in queues {
+        ret.push_str(q.name.as_str());
+        ret.push_str(format!("  max jobs: {}", q.max_jobs).as_str());
+        ret.push_str("  path: ");
+        ret.push_str(q.download_path.as_str());
+        if let Some(speed_limit) = q.speed_limit {
+            ret.push_str(format!("  speed limit: {} B/s", speed_limit).as_str());
+        }
+        ret.push_str("\r\n");
+        ret.push_str(render_jobs(&q.jobs).as_str());
+    }
+
+    ret
+}
+
+fn render_groups(groups: &[GroupView]) -> String {
+    let mut ret = String::new();
+    for group in groups {
+        ret.push_str(group.name.as_str());
+        ret.push_str(format!("  ( {} / {} done )", group.done, group.jobs).as_str());
+        if let Some(hook) = &group.hook {
+            ret.push_str("  hook: ");
+            ret.push_str(hook.as_str());
+            ret.push_str(if group.fired { " (fired)" } else { " (pending)" });
+        }
+        if let Some(err) = &group.hook_error {
+            ret.push_str("  error: ");
+            ret.push_str(err.as_str());
+        }
+        ret.push_str("\r\n");
+    }
+
+    ret
+}
+
+fn render_schedules(schedules: &[ScheduleView]) -> String {
+    let mut ret = String::new();
+    for schedule in schedules {
+        ret.push_str(format!("[{}] ", schedule.id).as_str());
+        ret.push_str(schedule.url.as_str());
+        ret.push_str("  [");
+        ret.push_str(schedule.cron.as_str());
+        ret.push_str("]  next: ");
+        match schedule.next_fire {
+            Some(next_fire) => ret.push_str(next_fire.to_rfc3339().as_str()),
+            None => ret.push_str("never"),
+        }
+        ret.push_str("\r\n");
+    }
+
+    ret
+}
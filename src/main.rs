@@ -5,12 +5,13 @@ mod scheduler;
 use structopt::StructOpt;
 use commands::Manager;
 
-fn main() {
+#[tokio::main]
+async fn main() {
     // Receive cmd args
     let args = Manager::from_args();
 
     // execute commands
-    let res = args.handle();
+    let res = args.handle().await;
 
 
     // print response
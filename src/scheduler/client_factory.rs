@@ -0,0 +1,107 @@
+use crate::commands::ClientOptions;
+use reqwest::{Client, Proxy};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/*
+ * The ClientFactory owns the http clients of the scheduler
+ *
+ * - A client holds a pool of connections, so the executors share clients instead of creating their own
+ * - The pool, the user agent and http2 are set once for all clients by the Start command
+ * - The proxy and the tls settings of a job need their own client, the jobs with the same settings share it
+ * - The headers of a job are set on its requests, they don't need another client
+ * - A connection that can't be made within the connect timeout fails the download, the stall timeout is applied by the executors
+ */
+
+pub const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+impl ClientOptions {
+    pub fn connect_timeout(&self) -> Duration {
+        self.connect_timeout.map(Duration::from_secs).unwrap_or(DEFAULT_CONNECT_TIMEOUT)
+    }
+
+    pub fn stall_timeout(&self) -> Duration {
+        self.stall_timeout.map(Duration::from_secs).unwrap_or(DEFAULT_STALL_TIMEOUT)
+    }
+}
+
+// the settings of a job that can't be changed per request
+#[derive(Debug, Default, Clone, Hash, PartialEq, Eq)]
+pub struct ClientKey {
+    pub proxy: Option<String>,
+    pub insecure: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct ClientFactory {
+    options: ClientOptions,
+    clients: HashMap<ClientKey, Client>,
+}
+
+impl ClientFactory {
+    pub fn new(options: ClientOptions) -> Self {
+        ClientFactory {
+            options,
+            clients: HashMap::new(),
+        }
+    }
+
+    // return the pooled client of the settings, it is created the first time the settings are used
+    pub fn client_for(&mut self, key: ClientKey) -> anyhow::Result<Client> {
+        if let Some(client) = self.clients.get(&key) {
+            return Ok(client.clone());
+        }
+
+        let client = self.build(&key)?;
+        self.clients.insert(key, client.clone());
+
+        Ok(client)
+    }
+
+    // the time an executor waits for the server before the download fails
+    pub fn stall_timeout(&self) -> Duration {
+        self.options.stall_timeout()
+    }
+
+    fn build(&self, key: &ClientKey) -> anyhow::Result<Client> {
+        let options = &self.options;
+        let user_agent = options.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+
+        // the idle connections are kept alive to reuse them
+        let mut builder = Client::builder()
+            .user_agent(user_agent)
+            .tcp_keepalive(Duration::from_secs(60))
+            .connect_timeout(options.connect_timeout());
+
+        if let Some(max_idle) = options.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(idle_timeout) = options.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(Duration::from_secs(idle_timeout));
+        }
+
+        // http2 is negotiated with the servers that support it only if it is enabled
+        builder = if options.http2_prior_knowledge {
+            builder.http2_prior_knowledge()
+        } else if options.http2 {
+            builder
+        } else {
+            builder.http1_only()
+        };
+
+        if let Some(proxy) = &key.proxy {
+            let proxy = Proxy::all(proxy.as_str())
+                .map_err(|e| anyhow::anyhow!("Invalid proxy '{}': {}", proxy, e))?;
+            builder = builder.proxy(proxy);
+        }
+        if key.insecure {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        builder
+            .build()
+            .map_err(|e| anyhow::anyhow!("Failed to create the http client: {}", e))
+    }
+}
@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/*
+ * A DownloadError is the reason a job didn't finish successfully
+ *
+ * - The kind of the error is kept so the tools that read the jobs can branch on it instead of parsing the message
+ * - Every variant keeps the underlying cause as text (the whole chain of sources), the http status keeps its code too
+ * - reqwest reports the dns and tls errors as connect errors, they are told apart by the messages of their sources
+ */
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DownloadError {
+    // the host name couldn't be resolved
+    Dns { cause: String },
+    // the connection was refused or dropped
+    Connect { cause: String },
+    // the tls handshake failed, like an invalid certificate
+    Tls { cause: String },
+    // the server answered with a status that isn't a success
+    HttpStatus { status: u16, cause: String },
+    Timeout { cause: String },
+    // the file couldn't be created or written
+    Io { cause: String },
+    DiskFull { cause: String },
+    // the downloaded file doesn't match the expected checksum
+    Checksum { expected: String, actual: String },
+    Cancelled { cause: String },
+    // the url, the headers or the proxy of the job are invalid
+    InvalidRequest { cause: String },
+    // the job it depends on didn't finish successfully
+    Blocked { after: usize },
+    // the executor panicked or stopped without finishing
+    Internal { cause: String },
+}
+
+impl DownloadError {
+    // the name of the kind, the same as the serialized one
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DownloadError::Dns { .. } => "dns",
+            DownloadError::Connect { .. } => "connect",
+            DownloadError::Tls { .. } => "tls",
+            DownloadError::HttpStatus { .. } => "http_status",
+            DownloadError::Timeout { .. } => "timeout",
+            DownloadError::Io { .. } => "io",
+            DownloadError::DiskFull { .. } => "disk_full",
+            DownloadError::Checksum { .. } => "checksum",
+            DownloadError::Cancelled { .. } => "cancelled",
+            DownloadError::InvalidRequest { .. } => "invalid_request",
+            DownloadError::Blocked { .. } => "blocked",
+            DownloadError::Internal { .. } => "internal",
+        }
+    }
+
+    pub fn invalid_request(cause: impl fmt::Display) -> Self {
+        DownloadError::InvalidRequest { cause: cause.to_string() }
+    }
+
+    pub fn internal(cause: impl fmt::Display) -> Self {
+        DownloadError::Internal { cause: cause.to_string() }
+    }
+
+    // a status that isn't a success, the cause is its reason phrase
+    pub fn http_status(status: reqwest::StatusCode) -> Self {
+        DownloadError::HttpStatus {
+            status: status.as_u16(),
+            cause: status.canonical_reason().unwrap_or("Unknown status").to_string(),
+        }
+    }
+
+    // the message of the error followed by the messages of its sources
+    // some errors already print their source, it isn't repeated
+    fn cause_of(e: &dyn Error) -> String {
+        let mut cause = e.to_string();
+        let mut source = e.source();
+        while let Some(e) = source {
+            let message = e.to_string();
+            if !cause.contains(message.as_str()) {
+                cause.push_str(": ");
+                cause.push_str(message.as_str());
+            }
+            source = e.source();
+        }
+
+        cause
+    }
+}
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(e: reqwest::Error) -> Self {
+        let cause = Self::cause_of(&e);
+
+        if e.is_timeout() {
+            return DownloadError::Timeout { cause };
+        }
+        if let Some(status) = e.status() {
+            return DownloadError::HttpStatus { status: status.as_u16(), cause };
+        }
+        if e.is_builder() {
+            return DownloadError::InvalidRequest { cause };
+        }
+
+        // hyper and native-tls don't expose their error kinds, so their messages are checked
+        let lower = cause.to_lowercase();
+        if lower.contains("dns error") || lower.contains("failed to lookup address") {
+            DownloadError::Dns { cause }
+        } else if lower.contains("certificate") || lower.contains("ssl") || lower.contains("tls") {
+            DownloadError::Tls { cause }
+        } else {
+            DownloadError::Connect { cause }
+        }
+    }
+}
+
+impl From<io::Error> for DownloadError {
+    fn from(e: io::Error) -> Self {
+        let cause = Self::cause_of(&e);
+
+        match e.kind() {
+            io::ErrorKind::StorageFull => DownloadError::DiskFull { cause },
+            io::ErrorKind::TimedOut => DownloadError::Timeout { cause },
+            _ => DownloadError::Io { cause },
+        }
+    }
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::Dns { cause } => write!(f, "DNS Error: {}", cause),
+            DownloadError::Connect { cause } => write!(f, "Connect Error: {}", cause),
+            DownloadError::Tls { cause } => write!(f, "TLS Error: {}", cause),
+            DownloadError::HttpStatus { status, cause } => write!(f, "HTTP Error {}: {}", status, cause),
+            DownloadError::Timeout { cause } => write!(f, "Timeout: {}", cause),
+            DownloadError::Io { cause } => write!(f, "IO Error: {}", cause),
+            DownloadError::DiskFull { cause } => write!(f, "Disk Full: {}", cause),
+            DownloadError::Checksum { expected, actual } => {
+                write!(f, "Checksum Error: expected {} but got {}", expected, actual)
+            }
+            DownloadError::Cancelled { cause } => write!(f, "Cancelled: {}", cause),
+            DownloadError::InvalidRequest { cause } => write!(f, "Invalid Request: {}", cause),
+            DownloadError::Blocked { after } => {
+                write!(f, "The job {} it depends on didn't finish successfully", after)
+            }
+            DownloadError::Internal { cause } => write!(f, "Internal Error: {}", cause),
+        }
+    }
+}
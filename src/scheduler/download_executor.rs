@@ -1,160 +1,365 @@
 use super::download_object::*;
+use super::download_queue::SpeedLimiter;
 use chrono::Utc;
-use reqwest::blocking::Client;
-use std::fs::{File, OpenOptions};
-use std::io::Write;
-use std::{
-    sync::mpsc::{self, Receiver},
-    sync::{Arc, Mutex},
-    thread,
-};
-
-type Msg = String;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+use super::scheduler_event::SchedulerEvent;
+use super::supervisor::{catch_panic, LockOrRecover};
+use super::download_error::DownloadError;
 
 /*
- * The DownloadExecutor's job is to take a reference to a download object and run it in a thread 
+ * The DownloadExecutor's job is to take a reference to a download object and run it in a task 
  * 
- * - When the file finish to download the thread die
- * - Before the thread die, it send the id of the active download_object to move it to done either it Done or it Failed 
+ * - When the file finish to download the task ends
+ * - The tasks share the client of the scheduler, so the connections to the same host are reused
+ * - The body is streamed to the disk chunk by chunk, the memory of a task doesn't grow with the file size
+ * - A server that doesn't answer or stops sending the body for the stall timeout fails the download with a timeout
+ * - While downloading, the task sends the number of bytes written as progress events (at most twice a second)
+ * - Before the task ends, it send the id of the active download_object to move it to done either it Done or it Failed 
 */
 
+// the time between two progress events of an executor
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+
+// the answer of the server to the download request
+enum Requested {
+    // the body is the whole file, or the rest of it if the server accepted the range of a resumed download
+    Body(Response),
+    // the server answered 304 and the existing file is kept
+    NotModified(Validators),
+}
 
 #[derive(Debug)]
 pub struct DownloadExecutor {
-    pub sched_rx: Receiver<usize>,
+    task: JoinHandle<()>,
 }
 
 impl DownloadExecutor {
-    pub fn new(id: usize, download_obj: Arc<Mutex<DownloadObject>>) -> Self {
-        let (thread_tx, sched_rx) = mpsc::channel();
-
-        thread::spawn(move || {
-            // creates client 
-            let client = Client::new();
-
-            // extract some values insted locking the mutex all the time to use the values
-            let (url, download_path, mut name) = {
-                let down_obj_ptr = Arc::clone(&download_obj);
-                let down_obj_ptr = down_obj_ptr.lock().unwrap();
-                (
-                    down_obj_ptr.url.clone(),
-                    down_obj_ptr.download_path.clone(),
-                    down_obj_ptr.name.clone(),
-                )
-            };
+    // the limiter is shared with the other executors of the same queue
+    pub fn new(
+        id: usize,
+        download_obj: Arc<Mutex<DownloadObject>>,
+        limiter: Option<Arc<Mutex<SpeedLimiter>>>,
+        client: Client,
+        stall_timeout: Duration,
+        events: Sender<SchedulerEvent>,
+    ) -> Self {
+        let task = tokio::spawn(async move {
+            // the download runs supervised, if it panics the job fails with the panic message
+            let result = catch_panic(Self::execute(id, &download_obj, limiter, client, stall_timeout, &events))
+                .await
+                .map_err(DownloadError::internal)
+                .and_then(|result| result);
 
-            // closure to send that the task id is failed
-            let send_failed = |e: String| {
+            if let Err(e) = result {
                 // updating the download metadata
-                let down_obj_ptr = Arc::clone(&download_obj);
-                let mut down_obj_ptr = down_obj_ptr.lock().unwrap();
-                down_obj_ptr.resulted_err = Some(e.clone());
+                let mut down_obj_ptr = download_obj.lock_or_recover();
                 down_obj_ptr.state = State::Failed;
                 down_obj_ptr.resulted_err = Some(e);
-                thread_tx.send(id).unwrap();
-            };
+            }
 
-            // check if file can be downloaded and update the total file size
-            match Self::does_it_can_be_downloaded(url.as_str(), &client) {
-                Ok(info) => {
-                    Arc::clone(&download_obj).lock().unwrap().total_size = info;
-                }
-                Err(e) => {
-                    send_failed(e);
-                    return;
-                }
+            // flag the scheduler
+            let _ = events.send(SchedulerEvent::Finished(id)).await;
+        });
+
+        Self { task }
+    }
+
+    async fn execute(
+        id: usize,
+        download_obj: &Mutex<DownloadObject>,
+        limiter: Option<Arc<Mutex<SpeedLimiter>>>,
+        client: Client,
+        stall_timeout: Duration,
+        events: &Sender<SchedulerEvent>,
+    ) -> Result<(), DownloadError> {
+        // extract some values insted locking the mutex all the time to use the values
+        let (url, download_path, name, only_if_changed, headers, resume) = {
+            let down_obj_ptr = download_obj.lock_or_recover();
+            (
+                down_obj_ptr.url.clone(),
+                down_obj_ptr.download_path.clone(),
+                down_obj_ptr.name.clone(),
+                down_obj_ptr.only_if_changed.clone(),
+                Self::header_map(&down_obj_ptr.headers),
+                down_obj_ptr.resume,
+            )
+        };
+        let headers = headers?;
+
+        // check if file can be downloaded and update the total file size
+        let info = Self::does_it_can_be_downloaded(url.as_str(), &client, &headers, stall_timeout).await?;
+        download_obj.lock_or_recover().total_size = info;
+
+        // a paused download continues from the end of its partial file
+        let resume_from = if resume {
+            Self::partial_size(download_path.as_str(), name.as_str()).await
+        } else {
+            None
+        };
+
+        // request the file, if the validators of a previous download are set the server may answer 304
+        let request = client.get(url.as_str()).headers(headers);
+        let response = match Self::request_the_file(request, only_if_changed.as_ref(), resume_from, stall_timeout).await? {
+            Requested::Body(response) => response,
+            Requested::NotModified(validators) => {
+                // the existing file is untouched, so it is still described by the previous validators
+                // unless the server sent new ones with the 304
+                let previous = only_if_changed.unwrap_or_default();
+                let mut down_obj_ptr = download_obj.lock_or_recover();
+                down_obj_ptr.state = State::NotModified;
+                down_obj_ptr.validators = Validators {
+                    etag: validators.etag.or(previous.etag),
+                    last_modified: validators.last_modified.or(previous.last_modified),
+                };
+                return Ok(());
             }
+        };
+        let validators = Self::validators_of(response.headers());
 
-            // download the file
-            let download_result =
-                Self::download_the_file(url.as_str(), &client, download_path.as_str(), &mut name);
+        // the server may ignore the range and send the whole file, then the partial file is overwritten
+        let offset = resume_from.filter(|_| response.status() == StatusCode::PARTIAL_CONTENT);
+        let (file, name) = if resume {
+            let file = Self::open_the_partial_file(download_path.as_str(), name.as_str(), offset.is_some()).await?;
+            (file, name)
+        } else {
+            Self::safely_open_the_file(download_path.as_str(), name.as_str()).await?
+        };
 
-            if let Err(e) = download_result {
-                send_failed(e);
-                return;
+        // the job knows its file from now on, if the download is paused it is resumed from this file
+        {
+            let mut down_obj_ptr = download_obj.lock_or_recover();
+            down_obj_ptr.name = name;
+            down_obj_ptr.file_opened = true;
+            if let Some(attempt) = down_obj_ptr.attempts.last_mut() {
+                attempt.resumed_from = offset.unwrap_or(0);
             }
+        }
 
+        // download the file
+        let size = Self::store_the_file(
+            response,
+            file,
+            offset.unwrap_or(0),
+            limiter.as_deref(),
+            stall_timeout,
+            |downloaded| {
+                // a progress event is dropped if the scheduler is busy, the next one replaces it anyway
+                let _ = events.try_send(SchedulerEvent::Progress { id, downloaded });
+            },
+        )
+        .await?;
 
-            // update the download object metadata
-            let down_obj_ptr = Arc::clone(&download_obj);
-            let mut down_obj_ptr = down_obj_ptr.lock().unwrap();
 
-            down_obj_ptr.state = State::Done;
-            down_obj_ptr.name = download_result.unwrap().unwrap();
+        // update the download object metadata
+        let mut down_obj_ptr = download_obj.lock_or_recover();
+        down_obj_ptr.state = State::Done;
+        down_obj_ptr.final_size = Some(size);
+        down_obj_ptr.validators = validators;
+        down_obj_ptr.resume = false;
 
-            // flag the main thread
-            thread_tx.send(id).unwrap();
-        });
+        Ok(())
+    }
+
+    // the task ended, if the job is still active it never reached the end of the download
+    pub fn is_dead(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    // stop the task where it is and wait for it, the partial file stays on the disk
+    pub async fn abort(self) {
+        self.task.abort();
+        let _ = self.task.await;
+    }
 
-        Self { sched_rx }
+    // wait for the task, it is called after the task sent that it finished
+    pub async fn join(self) {
+        let _ = self.task.await;
     }
 
     // send a head request and if there is a response then the file can be downloaded
     // if it can be downloaded the function return the total_size of the file
-    // if not return erorr message
-    fn does_it_can_be_downloaded(url: &str, client: &Client) -> Result<Option<u64>, Msg> {
+    // if not return the error
+    async fn does_it_can_be_downloaded(
+        url: &str,
+        client: &Client,
+        headers: &HeaderMap,
+        stall_timeout: Duration,
+    ) -> Result<Option<u64>, DownloadError> {
 
         // result will be ok if the file can be downloaded
-        let file_info = client.head(url).send();
+        let file_info = Self::before_stall(stall_timeout, client.head(url).headers(headers.clone()).send()).await?;
         match file_info {
             Ok(info) => {
-                let headers = info.headers();
-                if headers.contains_key("content-length") {
-                    // If content-length is found => no problem for the unwrap()
-                    return Ok(Some(
-                        headers
-                            .get("content-length")
-                            .unwrap()
-                            .to_str()
-                            .unwrap()
-                            .parse::<usize>()
-                            .unwrap() as u64,
-                    ));
-                }
+                // a missing or invalid content-length means the size is unknown
+                let total_size = info
+                    .headers()
+                    .get(CONTENT_LENGTH)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse::<u64>().ok());
+
+                Ok(total_size)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
 
-                Ok(None)
+    // send the download request, it is conditional if only_if_changed is set and a 304 leaves the disk untouched
+    // a resumed download asks only for the bytes after its partial file
+    async fn request_the_file(
+        request: RequestBuilder,
+        only_if_changed: Option<&Validators>,
+        resume_from: Option<u64>,
+        stall_timeout: Duration,
+    ) -> Result<Requested, DownloadError> {
+
+        // build the conditional request
+        let mut request = request;
+        if let Some(previous) = only_if_changed {
+            if let Some(etag) = &previous.etag {
+                request = request.header(IF_NONE_MATCH, etag.as_str());
+            }
+            if let Some(last_modified) = &previous.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
             }
-            Err(e) => Err(e.to_string()),
         }
+        if let Some(resume_from) = resume_from {
+            request = request.header(RANGE, format!("bytes={}-", resume_from));
+        }
+
+        let response = Self::before_stall(stall_timeout, request.send()).await??;
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Requested::NotModified(Self::validators_of(response.headers())));
+        }
+
+        // an error page of the server is not the file
+        if !response.status().is_success() {
+            return Err(DownloadError::http_status(response.status()));
+        }
+
+        Ok(Requested::Body(response))
     }
 
     // This function download the file and save it
     // again the function doesn't return the file, it stores it directly
-    // if the file stored well the function return the filename
-    fn download_the_file(
-        url: &str,
-        client: &Client,
-        download_path: &str,
-        file_name: &mut String,
-    ) -> Result<Option<String>, Msg> {
+    // the offset is the size of the partial file the body is appended to
+    // if the file stored well the function return its size
+    async fn store_the_file(
+        response: Response,
+        mut file: File,
+        offset: u64,
+        limiter: Option<&Mutex<SpeedLimiter>>,
+        stall_timeout: Duration,
+        mut progress: impl FnMut(u64),
+    ) -> Result<u64, DownloadError> {
+        let mut response = response;
 
-        // download the file
-        let downloaded_file = client.get(url).send().map_err(|e| e.to_string())?;
-        let (mut file, file_name) = Self::safely_open_the_file(download_path, file_name);
+        //store it chunk by chunk, so the speed limit can hold the reading back
+        let mut size = offset;
+        let mut last_progress = Instant::now();
+        // the wait of the speed limit isn't a stall, only the wait for the next chunk is timed
+        while let Some(chunk) = Self::before_stall(stall_timeout, response.chunk()).await?? {
+            file.write_all(&chunk).await?;
+            size += chunk.len() as u64;
+
+            if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                progress(size);
+                last_progress = Instant::now();
+            }
+
+            if let Some(limiter) = limiter {
+                let wait = limiter.lock_or_recover().consume(chunk.len() as u64);
+                tokio::time::sleep(wait).await;
+            }
+        }
+        file.flush().await?;
 
-        //store it
-        let _written = file
-            .write(&downloaded_file.bytes().map_err(|_e| String::from("Encoding Error: Failed to save the file"))?)
-            .map_err(|e| e.to_string())?;
+        progress(size);
 
-        // return the final file_name
-        Ok(Some(file_name))
+        Ok(size)
     }
 
+    // wait for the server, a server that sends nothing for the stall timeout fails the download
+    async fn before_stall<T>(stall_timeout: Duration, future: impl Future<Output = T>) -> Result<T, DownloadError> {
+        tokio::time::timeout(stall_timeout, future).await.map_err(|_| DownloadError::Timeout {
+            cause: format!("The server sent nothing for {} seconds", stall_timeout.as_secs()),
+        })
+    }
+
+    // the headers of the job were checked when it was added, but a retry of an old job may fail
+    fn header_map(headers: &[(String, String)]) -> Result<HeaderMap, DownloadError> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(DownloadError::invalid_request)?;
+            let value = HeaderValue::from_str(value).map_err(DownloadError::invalid_request)?;
+            map.append(name, value);
+        }
+
+        Ok(map)
+    }
+
+    // extract the headers that identify the version of the downloaded file
+    fn validators_of(headers: &HeaderMap) -> Validators {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+
+        Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+
+    // the size of the partial file of a paused download, None if it is missing or empty
+    async fn partial_size(download_path: &str, file_name: &str) -> Option<u64> {
+        tokio::fs::metadata(format!("{}/{}", download_path, file_name))
+            .await
+            .ok()
+            .map(|metadata| metadata.len())
+            .filter(|size| *size > 0)
+    }
+
+    // open the partial file of a paused download to append the rest of it, or to overwrite it
+    async fn open_the_partial_file(download_path: &str, file_name: &str, append: bool) -> std::io::Result<File> {
+        let _ = tokio::fs::create_dir_all(download_path).await;
+
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(format!("{}/{}", download_path, file_name))
+            .await
+    }
 
     // safely because if the download_path is not exist the function will create it for the user
     // and if the filename exists it will append a time stamp to the file_name to make the it unique
-    fn safely_open_the_file(download_path: &str, file_name: &str) -> (File, String) {
+    async fn safely_open_the_file(download_path: &str, file_name: &str) -> std::io::Result<(File, String)> {
 
         // creates all non-exists directories
-        let _ = std::fs::create_dir_all(download_path);
+        let _ = tokio::fs::create_dir_all(download_path).await;
 
-        // make the file_name unique
-        let (filename, extension) = file_name.rsplit_once(".").unwrap();
+        // make the file_name unique, the time stamp goes before the extension if there is one
         let file_name =
-            if std::fs::metadata(format!("{}/{}", download_path, file_name)).is_ok() {
+            if tokio::fs::metadata(format!("{}/{}", download_path, file_name)).await.is_ok() {
                 let unique_name = Utc::now().format("%Y_%b_%d_%H_%M_%S").to_string();
-                format!("{}_{}.{}", filename, unique_name, extension)
+                match file_name.rsplit_once(".") {
+                    Some((filename, extension)) => format!("{}_{}.{}", filename, unique_name, extension),
+                    None => format!("{}_{}", file_name, unique_name),
+                }
             } else {
                 file_name.to_owned()
             };
@@ -164,8 +369,9 @@ impl DownloadExecutor {
             .read(true)
             .write(true)
             .create(true)
-            .open(format!("{}/{}", download_path, file_name));
+            .open(format!("{}/{}", download_path, file_name))
+            .await;
 
-        (file_result.unwrap(), file_name)
+        Ok((file_result?, file_name))
     }
 }
@@ -0,0 +1,59 @@
+use tokio::process::Command;
+
+/*
+ * A DownloadGroup is a name shared by download objects added with the same group
+ *
+ * - The scheduler fires the hook of the group once all of its download objects are done successfully
+ * - The hook is a shell command, it gets the group name in the MANAGER_GROUP environment variable
+ * - The hook runs on its own, a task waits for it so it doesn't stay a zombie process
+ * - Adding a new download object to a fired group re-arms the hook
+ */
+
+#[derive(Debug)]
+pub struct DownloadGroup {
+    pub name: String,
+    pub hook: Option<String>,
+    pub fired: bool,
+    pub hook_err: Option<String>,
+}
+
+impl DownloadGroup {
+    pub fn new(name: String) -> Self {
+        DownloadGroup {
+            name,
+            hook: None,
+            fired: false,
+            hook_err: None,
+        }
+    }
+
+    // start the hook without waiting for it, the scheduler loop must not block
+    pub fn fire(&mut self) {
+        self.fired = true;
+
+        let hook = match &self.hook {
+            Some(hook) => hook,
+            None => return,
+        };
+
+        let mut command = if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.arg("/C");
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.arg("-c");
+            command
+        };
+
+        match command.arg(hook).env("MANAGER_GROUP", self.name.as_str()).spawn() {
+            Ok(mut child) => {
+                self.hook_err = None;
+                tokio::spawn(async move {
+                    let _ = child.wait().await;
+                });
+            }
+            Err(e) => self.hook_err = Some(e.to_string()),
+        }
+    }
+}
@@ -1,37 +1,249 @@
+use super::download_error::DownloadError;
+use crate::commands::AddOptions;
+use chrono::{DateTime, Duration, Utc};
 use serde::{Serialize, Deserialize};
+use std::fmt;
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum State {
+    #[default]
     Waiting,
     Active,
     Done,
+    NotModified,
     Failed,
+    // a job it depends on didn't finish successfully so it never started
+    Blocked,
+    // the manager shut down while the job was active, it resumes its partial file on the next start
+    Paused,
 }
 
-impl Default for State {
-    fn default() -> Self { State::Waiting }
+impl State {
+    pub const ALL: [State; 7] = [
+        State::Waiting,
+        State::Active,
+        State::Done,
+        State::NotModified,
+        State::Failed,
+        State::Blocked,
+        State::Paused,
+    ];
+
+    // the name of a state in any case, like done or Done
+    pub fn parse(state: &str) -> anyhow::Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|s| s.to_string().eq_ignore_ascii_case(state))
+            .ok_or_else(|| anyhow::anyhow!("Invalid state '{}'", state))
+    }
 }
 
-impl ToString for State {
-    fn to_string(&self) -> String {
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            State::Waiting => "Waiting".to_string(),
-            State::Active => "Active".to_string(),
-            State::Done => "Done".to_string(),
-            State::Failed => "Failed".to_string(),
+            State::Waiting => write!(f, "Waiting"),
+            State::Active => write!(f, "Active"),
+            State::Done => write!(f, "Done"),
+            State::NotModified => write!(f, "NotModified"),
+            State::Failed => write!(f, "Failed"),
+            State::Blocked => write!(f, "Blocked"),
+            State::Paused => write!(f, "Paused"),
         }
     }
 }
 
 
+// the headers a server sends to identify a version of a file
+// if two responses carry the same validators the file did not change between them
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
 // should be renamed to DownloadFileMetadata
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DownloadObject {
+    pub id: usize,
     pub name: String,
     pub download_path: String,
     pub url: String,
     pub state: State,
     pub total_size: Option<u64>,
-    pub resulted_err: Option<String>,
+    // the number of bytes written so far, updated by the progress events of the executor
+    pub downloaded_size: u64,
+    // the number of bytes written to the disk
+    pub final_size: Option<u64>,
+    // the reason the job failed or was blocked
+    pub resulted_err: Option<DownloadError>,
+    // the validators the server sent for this download
+    pub validators: Validators,
+    // if set the download is conditional and a 304 from the server leaves the existing file untouched
+    pub only_if_changed: Option<Validators>,
+    // the id of the schedule that enqueued this download
+    pub schedule: Option<usize>,
+    // the ids of the jobs that must be done before this one starts
+    pub after: Vec<usize>,
+    pub group: Option<String>,
+    pub queue: String,
+    // the options the job was added with, a retry or a clone starts from them
+    pub options: AddOptions,
+    // the headers sent with the requests of the job
+    pub headers: Vec<(String, String)>,
+    // the time the job was added
+    pub created_at: DateTime<Utc>,
+    // the time the job entered the waiting list, a retry sets it again
+    pub queued_at: DateTime<Utc>,
+    // the time the last attempt started
+    pub started_at: Option<DateTime<Utc>>,
+    // the time the job moved to the done list
+    pub finished_at: Option<DateTime<Utc>>,
+    // every run of the job, a retry adds another one
+    pub attempts: Vec<Attempt>,
+    // the executor opened the file named by the job, before that the name may be the one of another file
+    pub file_opened: bool,
+    // the file named by the job is the partial file of a paused download, it is resumed with a range request
+    pub resume: bool,
+}
+
+impl DownloadObject {
+    // the time the job waited in the waiting list, it is still growing for a waiting job
+    pub fn queue_wait(&self) -> Duration {
+        self.started_at.unwrap_or_else(Utc::now) - self.queued_at
+    }
+
+    // the job wrote the file it names, a not modified job left the file of an earlier download untouched
+    pub fn wrote_file(&self) -> bool {
+        self.state != State::NotModified && (self.file_opened || self.final_size.is_some() || self.downloaded_size > 0)
+    }
+
+    // the last attempt ends with the state the job ended with
+    pub fn close_attempt(&mut self, now: DateTime<Utc>) {
+        let (state, size) = (self.state, self.final_size.unwrap_or(self.downloaded_size));
+        if let Some(attempt) = self.attempts.last_mut() {
+            attempt.finished_at = Some(now);
+            attempt.downloaded = size.saturating_sub(attempt.resumed_from);
+            attempt.state = state;
+        }
+    }
+
+    // the average speed of the last attempt in bytes per second
+    pub fn throughput(&self) -> Option<f64> {
+        self.attempts.last().and_then(Attempt::throughput)
+    }
+
+    // the average speed of an active job since it started, in bytes per second
+    // the bytes of the partial file a resumed run continues from aren't part of it
+    pub fn live_throughput(&self, now: DateTime<Utc>) -> Option<f64> {
+        let resumed_from = self.attempts.last().map_or(0, |attempt| attempt.resumed_from);
+        self.started_at
+            .map(|started_at| (now - started_at).num_milliseconds())
+            .filter(|millis| *millis > 0)
+            .map(|millis| self.downloaded_size.saturating_sub(resumed_from) as f64 * 1000.0 / millis as f64)
+    }
+
+    // the value a list is sorted by, the jobs without one go last
+    pub fn sort_value(&self, key: SortKey) -> Option<f64> {
+        let millis = |time: DateTime<Utc>| time.timestamp_millis() as f64;
+        match key {
+            SortKey::Id => Some(self.id as f64),
+            SortKey::Created => Some(millis(self.created_at)),
+            SortKey::Started => self.started_at.map(millis),
+            SortKey::Finished => self.finished_at.map(millis),
+            SortKey::Duration => self
+                .attempts
+                .last()
+                .and_then(Attempt::duration)
+                .map(|duration| duration.num_milliseconds() as f64),
+            SortKey::Throughput => self.throughput(),
+            SortKey::Wait => Some(self.queue_wait().num_milliseconds() as f64),
+        }
+    }
+}
+
+
+// one run of a job by an executor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attempt {
+    // the time the job entered the waiting list before this run
+    pub queued_at: DateTime<Utc>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    // the size of the partial file a resumed run continues from
+    #[serde(default)]
+    pub resumed_from: u64,
+    // the number of bytes written by this run
+    pub downloaded: u64,
+    pub state: State,
+}
+
+impl Attempt {
+    pub fn new(queued_at: DateTime<Utc>, started_at: DateTime<Utc>, resumed_from: u64) -> Self {
+        Attempt {
+            queued_at,
+            started_at,
+            finished_at: None,
+            resumed_from,
+            downloaded: 0,
+            state: State::Active,
+        }
+    }
+
+    pub fn wait(&self) -> Duration {
+        self.started_at - self.queued_at
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.finished_at.map(|finished_at| finished_at - self.started_at)
+    }
+
+    // a run shorter than a millisecond has no meaningful speed
+    pub fn throughput(&self) -> Option<f64> {
+        let millis = self.duration()?.num_milliseconds();
+        if millis <= 0 {
+            return None;
+        }
+
+        Some(self.downloaded as f64 * 1000.0 / millis as f64)
+    }
+}
+
+
+// the values the jobs of a list can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Id,
+    Created,
+    Started,
+    Finished,
+    Duration,
+    Throughput,
+    Wait,
+}
+
+// the order of a list, the key and its direction
+#[derive(Debug, Clone, Copy)]
+pub struct ListOrder {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl SortKey {
+    pub fn parse(key: &str) -> anyhow::Result<Self> {
+        match key.to_lowercase().as_str() {
+            "id" => Ok(SortKey::Id),
+            "created" => Ok(SortKey::Created),
+            "started" => Ok(SortKey::Started),
+            "finished" => Ok(SortKey::Finished),
+            "duration" => Ok(SortKey::Duration),
+            "throughput" => Ok(SortKey::Throughput),
+            "wait" => Ok(SortKey::Wait),
+            _ => Err(anyhow::anyhow!(
+                "Invalid sort key '{}', use id, created, started, finished, duration, throughput or wait",
+                key
+            )),
+        }
+    }
 }
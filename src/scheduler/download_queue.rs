@@ -0,0 +1,67 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/*
+ * A DownloadQueue is a named set of jobs with its own limits
+ *
+ * - Every queue has its own number of active downloads, the scheduler starts a job only if its queue has a free slot
+ * - The download path of the queue is used for its jobs that don't set a custom one
+ * - The speed limit is shared by all active jobs of the queue through one SpeedLimiter
+ */
+
+pub const DEFAULT_QUEUE: &str = "default";
+
+#[derive(Debug)]
+pub struct DownloadQueue {
+    pub name: String,
+    pub max_jobs: usize,
+    pub download_path: Option<String>,
+    pub speed_limit: Option<u64>,
+    pub limiter: Option<Arc<Mutex<SpeedLimiter>>>,
+}
+
+impl DownloadQueue {
+    pub fn new(
+        name: String,
+        max_jobs: usize,
+        download_path: Option<String>,
+        speed_limit: Option<u64>,
+    ) -> Self {
+        let limiter = speed_limit.map(|limit| Arc::new(Mutex::new(SpeedLimiter::new(limit))));
+
+        DownloadQueue {
+            name,
+            max_jobs,
+            download_path,
+            speed_limit,
+            limiter,
+        }
+    }
+}
+
+
+// the executors report every chunk they read and sleep for the returned duration
+// so the sum of the speeds of the executors sharing the limiter stays under the limit
+#[derive(Debug)]
+pub struct SpeedLimiter {
+    bytes_per_second: u64,
+    // the time the bytes consumed so far are allowed to be finished at
+    next: Instant,
+}
+
+impl SpeedLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        SpeedLimiter {
+            bytes_per_second: bytes_per_second.max(1),
+            next: Instant::now(),
+        }
+    }
+
+    pub fn consume(&mut self, bytes: u64) -> Duration {
+        let now = Instant::now();
+        let cost = Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+        self.next = self.next.max(now) + cost;
+
+        self.next.saturating_duration_since(now)
+    }
+}
@@ -0,0 +1,89 @@
+use super::download_object::Validators;
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use std::str::FromStr;
+
+/*
+ * A DownloadSchedule is a url that is downloaded again every time its cron expression fires
+ *
+ * - The scheduler checks the schedules on every loop and enqueues a fresh download object for each due one
+ * - The name of every download is rendered from the name template, so the copies don't overwrite each other
+ * - The schedule remembers the validators of its last finished download to skip the unchanged files
+ * - A schedule has an id of its own, it stays the same when another schedule is removed
+ */
+
+pub const DEFAULT_NAME_TEMPLATE: &str = "{date}-{name}";
+
+#[derive(Debug)]
+pub struct DownloadSchedule {
+    pub id: usize,
+    pub url: String,
+    pub cron: String,
+    pub download_path: String,
+    pub name_template: String,
+    pub next_fire: Option<DateTime<Utc>>,
+    pub validators: Option<Validators>,
+    schedule: Schedule,
+}
+
+impl DownloadSchedule {
+    pub fn new(
+        id: usize,
+        url: String,
+        cron: String,
+        download_path: String,
+        name_template: String,
+    ) -> anyhow::Result<Self> {
+        // the usual cron expressions have five fields, the cron crate expects the seconds too
+        let expression = if cron.split_whitespace().count() == 5 {
+            format!("0 {}", cron)
+        } else {
+            cron.clone()
+        };
+
+        let schedule = Schedule::from_str(expression.as_str())
+            .map_err(|e| anyhow::anyhow!("Invalid cron expression '{}': {}", cron, e))?;
+        let next_fire = schedule.upcoming(Utc).next();
+
+        Ok(DownloadSchedule {
+            id,
+            url,
+            cron,
+            download_path,
+            name_template,
+            next_fire,
+            validators: None,
+            schedule,
+        })
+    }
+
+    // returns true if the schedule is due and moves it to its next fire time
+    pub fn fire(&mut self, now: DateTime<Utc>) -> bool {
+        match self.next_fire {
+            Some(next_fire) if next_fire <= now => {
+                self.next_fire = self.schedule.after(&now).next();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // fills the template placeholders: {name} is the file name in the url without its extension
+    // {date} and {time} are the time the schedule fired
+    pub fn render_name(&self, now: DateTime<Utc>) -> String {
+        let file_name = self
+            .url
+            .rsplit_once('/')
+            .map(|(_, f)| f)
+            .unwrap_or(self.url.as_str());
+        let name = file_name
+            .rsplit_once('.')
+            .map(|(n, _)| n)
+            .unwrap_or(file_name);
+
+        self.name_template
+            .replace("{name}", name)
+            .replace("{date}", now.format("%Y-%m-%d").to_string().as_str())
+            .replace("{time}", now.format("%H-%M-%S").to_string().as_str())
+    }
+}
@@ -1,16 +1,32 @@
 use super::download_object::*;
-use crate::commands::{Manager, ManagerCommands};
-use std::collections::HashMap;
-use std::sync::mpsc::{Receiver, Sender};
+use crate::commands::{
+    AddOptions, CloneOverrides, ListCommands, Manager, ManagerCommands, QueueCommands, RetentionPolicy,
+};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use super::scheduler_event::SchedulerEvent;
+use super::client_factory::{ClientFactory, ClientKey};
+use super::supervisor::{catch_panic, LockOrRecover};
+use super::job_request::JobRequest;
+use super::response::{ApiError, GroupView, JobView, QueueView, Reply, ResponseBody, ScheduleView};
+use super::download_error::DownloadError;
+use super::saved_state::{SavedGroup, SavedQueue, SavedSchedule, SavedState};
+use super::job_event::{EventBus, JobEventKind};
+use super::metrics::{ActiveJob, Snapshot};
 use std::time::Duration;
 use std::vec;
 use std::{
     collections::VecDeque,
     sync::{Arc, Mutex},
-    thread,
 };
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
 
 use super::download_executor::DownloadExecutor;
+use super::download_schedule::{DownloadSchedule, DEFAULT_NAME_TEMPLATE};
+use super::download_group::DownloadGroup;
+use super::download_queue::{DownloadQueue, DEFAULT_QUEUE};
+use chrono::{DateTime, Utc};
 
 
 /*
@@ -18,11 +34,26 @@ use super::download_executor::DownloadExecutor;
  * - The most important function is the run function
  * - The scheduler has three lists [waiting, active, done]
  * - when calling the add function the download object is inserted in the waiting list
- * - if the running threads are less than the maximium jobs a download object moves to the active list
- * - if a running thread dies or finishes the download object moves to the done list
- * - the fourth list is the DownloadExecutor which creates a therad and hold the receiver end to the thread
+ * - if the running tasks of its queue are less than the maximium jobs of the queue a download object moves to the active list
+ * - if a running task dies or finishes the download object moves to the done list
+ * - the fourth list is the DownloadExecutor which creates a task, the task reports its progress and its end as events
+ * - only the active download objects have a task, the waiting ones are just their metadata however long the list is
+ * - the tasks share the http clients of the client factory and their pools of connections
+ * - the schedules are not a list of jobs, they add a new download object to the waiting list every time they fire
+ * - a download object waits until the jobs it depends on are done, if one of them fails it moves to the done list as Blocked
+ * - a group runs its hook once all of its download objects are done successfully
+ * - the default queue takes the max jobs and download path of the Start command, the other queues are created at runtime
+ * - a failed job can be moved back from the done list to the waiting list, a finished one can be added again as a new job
+ * - a cancelled job moves to the done list as failed, so it can be retried like the others
+ * - the retention policy drops the oldest jobs from the done list on every loop
+ * - the Shutdown command stops admitting jobs, the active ones finish or are paused at the deadline
+ *   then the lists are saved in the download path and restored by the next Start
+ * - every change of a job is published on the event bus, the progress of the active jobs on every tick
  */
 
+// the seconds the Shutdown command waits for the active downloads by default
+pub const SHUTDOWN_TIMEOUT: u64 = 30;
+
 
 
 #[derive(Debug, Default)]
@@ -31,157 +62,1382 @@ pub struct Scheduler {
     active_list: HashMap<usize, Arc<Mutex<DownloadObject>>>,
     done_list: Vec<Arc<Mutex<DownloadObject>>>,
     download_executor: HashMap<usize, DownloadExecutor>,
+    schedules: Vec<DownloadSchedule>,
+    groups: HashMap<String, DownloadGroup>,
+    queues: BTreeMap<String, DownloadQueue>,
+    next_id: usize,
+    next_schedule_id: usize,
     pub download_path: String,
-    pub max_jobs: usize,
+    retention: RetentionPolicy,
+    pub client_factory: ClientFactory,
+    // set by the Shutdown command, the active downloads that didn't finish by then are paused
+    shutdown_deadline: Option<DateTime<Utc>>,
+    bus: EventBus,
 }
 
 impl Scheduler {
     pub fn new(max_jobs: usize, download_path: String) -> Self {
+        let mut queues = BTreeMap::new();
+        queues.insert(
+            DEFAULT_QUEUE.to_string(),
+            DownloadQueue::new(DEFAULT_QUEUE.to_string(), max_jobs, None, None),
+        );
+
         Scheduler {
-            max_jobs,
             download_path,
+            queues,
             ..Default::default()
         }
     }
 
     // when calling the add function the download object is inserted in the waiting list
-    pub fn add(
+    // if only_if_changed is set and the url was downloaded before, the download is conditional
+    // on the validators of the last finished one
+    // the download object doesn't start before the jobs in after are done, the function returns its id
+    pub fn add(&mut self, options: AddOptions) -> anyhow::Result<usize> {
+        let original = options.clone();
+        let AddOptions {
+            url,
+            custom_name,
+            custom_download_path,
+            only_if_changed,
+            after,
+            group,
+            queue,
+            proxy,
+            insecure,
+            headers,
+        } = options;
+
+        if let Some(id) = after.iter().find(|id| self.find(**id).is_none()) {
+            return Err(anyhow::anyhow!("There is no job with the id {}", id));
+        }
+
+        // create the client of the job now so an invalid proxy is reported to the user
+        self.client_factory.client_for(ClientKey { proxy, insecure })?;
+        let headers = Self::parse_headers(headers)?;
+
+        let queue = queue.unwrap_or_else(|| DEFAULT_QUEUE.to_string());
+        let queue_path = match self.queues.get(&queue) {
+            Some(q) => q.download_path.clone(),
+            None => return Err(anyhow::anyhow!("There is no queue with the name {}", queue)),
+        };
+
+        // the custom path wins over the path of the queue and the path of the queue wins over the default one
+        let download_path = custom_download_path
+            .or(queue_path)
+            .unwrap_or_else(|| self.download_path.clone());
+
+        let mut obj = self.new_download_object(custom_name, download_path, url);
+        if only_if_changed {
+            obj.only_if_changed = self.last_validators_of(obj.url.as_str());
+        }
+        obj.after = after;
+        obj.queue = queue;
+        obj.headers = headers;
+        obj.options = original;
+
+        // a new job re-arms the hook of its group
+        if let Some(group) = &group {
+            self.rearm_group(group.clone());
+        }
+        obj.group = group;
+
+        let id = obj.id;
+        self.bus.publish(&obj, JobEventKind::Added { url: obj.url.clone() });
+        self.waiting_list.push_back(Arc::new(Mutex::new(obj)));
+
+        Ok(id)
+    }
+
+    // every header is a name and a value separated by a colon
+    fn parse_headers(headers: Vec<String>) -> anyhow::Result<Vec<(String, String)>> {
+        headers
+            .into_iter()
+            .map(|header| match header.split_once(':') {
+                Some((name, value)) => Ok((name.trim().to_string(), value.trim().to_string())),
+                None => Err(anyhow::anyhow!("Invalid header '{}', use 'Name: value'", header)),
+            })
+            .collect()
+    }
+
+    fn rearm_group(&mut self, group: String) {
+        self.groups
+            .entry(group.clone())
+            .or_insert_with(|| DownloadGroup::new(group))
+            .fired = false;
+    }
+
+    // move a failed, cancelled or blocked job back to the waiting list, it keeps its id and its original options
+    pub fn retry(&mut self, id: usize) -> anyhow::Result<()> {
+        let index = self
+            .done_list
+            .iter()
+            .position(|obj| obj.lock_or_recover().id == id)
+            .ok_or_else(|| anyhow::anyhow!("There is no finished job with the id {}", id))?;
+
+        {
+            let obj = self.done_list[index].lock_or_recover();
+            if !matches!(obj.state, State::Failed | State::Blocked) {
+                return Err(anyhow::anyhow!("The job {} is {}, only failed jobs can be retried", id, obj.state));
+            }
+            if !self.queues.contains_key(&obj.queue) {
+                return Err(anyhow::anyhow!("The queue {} of the job {} was deleted", obj.queue, id));
+            }
+            // the retry downloads the file again from its start, the partial file of the failed run would stay orphaned
+            // a file that can't be deleted keeps the job failed
+            Self::delete_file(&obj)?;
+        }
+
+        let obj = self.done_list.remove(index);
+        let group = {
+            let mut obj = obj.lock_or_recover();
+            obj.state = State::Waiting;
+            obj.queued_at = Utc::now();
+            obj.started_at = None;
+            obj.finished_at = None;
+            obj.resulted_err = None;
+            obj.downloaded_size = 0;
+            obj.total_size = None;
+            obj.final_size = None;
+            obj.validators = Validators::default();
+            obj.file_opened = false;
+            obj.resume = false;
+            self.bus.publish(&obj, JobEventKind::Retrying);
+            obj.group.clone()
+        };
+        if let Some(group) = group {
+            self.rearm_group(group);
+        }
+        self.waiting_list.push_back(obj);
+
+        Ok(())
+    }
+
+    // retry every failed or blocked job, the function returns how many were moved to the waiting list
+    pub fn retry_all_failed(&mut self) -> usize {
+        let failed: Vec<usize> = self
+            .done_list
+            .iter()
+            .map(|obj| obj.lock_or_recover())
+            .filter(|obj| matches!(obj.state, State::Failed | State::Blocked))
+            .map(|obj| obj.id)
+            .collect();
+
+        failed.into_iter().filter(|id| self.retry(*id).is_ok()).count()
+    }
+
+    // add a finished job again as a new job, it doesn't wait for its old dependencies
+    // and it is downloaded even if the file didn't change
+    pub fn redownload(&mut self, id: usize) -> anyhow::Result<usize> {
+        let mut options = self.finished_options(id)?;
+        options.after.clear();
+        options.only_if_changed = false;
+
+        self.add(options)
+    }
+
+    // add a new job with the options of an old one, the set overrides replace the old options
+    pub fn clone_job(&mut self, id: usize, overrides: CloneOverrides) -> anyhow::Result<usize> {
+        let mut options = self
+            .find(id)
+            .map(|obj| obj.lock_or_recover().options.clone())
+            .ok_or_else(|| anyhow::anyhow!("There is no job with the id {}", id))?;
+
+        let CloneOverrides {
+            url,
+            custom_name,
+            custom_download_path,
+            group,
+            queue,
+            proxy,
+            insecure,
+            headers,
+        } = overrides;
+        options.url = url.unwrap_or(options.url);
+        options.custom_name = custom_name.or(options.custom_name);
+        options.custom_download_path = custom_download_path.or(options.custom_download_path);
+        options.group = group.or(options.group);
+        options.queue = queue.or(options.queue);
+        options.proxy = proxy.or(options.proxy);
+        options.insecure |= insecure;
+        if !headers.is_empty() {
+            options.headers = headers;
+        }
+
+        self.add(options)
+    }
+
+    // drop a waiting or finished job, the file is deleted only if the job wrote it
+    // the file is deleted first, a job whose file can't be deleted stays in its list
+    pub fn remove(&mut self, id: usize, delete_file: bool) -> anyhow::Result<()> {
+        if self.active_list.contains_key(&id) {
+            return Err(anyhow::anyhow!("The job {} is active, cancel it first", id));
+        }
+        if self.is_dependency(id) {
+            return Err(anyhow::anyhow!("A waiting job depends on the job {}", id));
+        }
+
+        let obj = self
+            .waiting_list
+            .iter()
+            .chain(self.done_list.iter())
+            .find(|obj| obj.lock_or_recover().id == id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("There is no job with the id {}", id))?;
+        if delete_file {
+            Self::delete_file(&obj.lock_or_recover())?;
+        }
+
+        self.waiting_list.retain(|other| !Arc::ptr_eq(other, &obj));
+        self.done_list.retain(|other| !Arc::ptr_eq(other, &obj));
+        self.bus.publish(&obj.lock_or_recover(), JobEventKind::Removed);
+
+        Ok(())
+    }
+
+    // stop a waiting, paused or active job, it moves to the done list as failed with a cancelled error
+    // a retry downloads it again and remove drops it, its partial file is kept unless it is deleted
+    pub async fn cancel(&mut self, id: usize, delete_file: bool) -> anyhow::Result<()> {
+        if let Some(de) = self.download_executor.remove(&id) {
+            de.abort().await;
+        }
+
+        let finished = self
+            .active_list
+            .get(&id)
+            .is_some_and(|obj| obj.lock_or_recover().state != State::Active);
+        if finished {
+            // the event of its end is still in the channel, the scheduler ignores it after this
+            self.finish(id).await;
+            return Err(anyhow::anyhow!("The job {} finished before it was cancelled", id));
+        }
+
+        let obj = if let Some(obj) = self.active_list.remove(&id) {
+            obj
+        } else if let Some(index) = self.waiting_list.iter().position(|obj| obj.lock_or_recover().id == id) {
+            self.waiting_list.remove(index).unwrap()
+        } else {
+            return Err(anyhow::anyhow!("The job {} is finished, it can't be cancelled", id));
+        };
+
+        {
+            let mut obj_ptr = obj.lock_or_recover();
+            let now = Utc::now();
+            let was_active = obj_ptr.state == State::Active;
+            obj_ptr.state = State::Failed;
+            obj_ptr.finished_at = Some(now);
+            // only an active job has an attempt that is still running
+            if was_active {
+                obj_ptr.close_attempt(now);
+            }
+            obj_ptr.resume = false;
+            obj_ptr.resulted_err = Some(DownloadError::Cancelled {
+                cause: String::from("Cancelled by the user"),
+            });
+            self.bus.publish(&obj_ptr, JobEventKind::Failed { error: obj_ptr.resulted_err.clone() });
+        }
+        self.done_list.push(Arc::clone(&obj));
+
+        if delete_file {
+            Self::delete_file(&obj.lock_or_recover())?;
+        }
+
+        Ok(())
+    }
+
+    // move a waiting or paused job in the waiting list, the jobs before it are started first
+    // a position after the end moves it to the end
+    pub fn move_waiting(&mut self, id: usize, position: usize) -> anyhow::Result<()> {
+        let index = self
+            .waiting_list
+            .iter()
+            .position(|obj| obj.lock_or_recover().id == id)
+            .ok_or_else(|| anyhow::anyhow!("The job {} is not waiting, only waiting jobs can be moved", id))?;
+
+        let obj = self.waiting_list.remove(index).unwrap();
+        let position = position.min(self.waiting_list.len());
+        self.bus.publish(&obj.lock_or_recover(), JobEventKind::Moved { position });
+        self.waiting_list.insert(position, obj);
+
+        Ok(())
+    }
+
+    // drop the finished jobs that match all the set filters, the function returns how many were dropped
+    // and the errors of the jobs that couldn't be, the other jobs are dropped anyway
+    // the jobs a waiting job depends on are kept
+    pub fn purge(
+        &mut self,
+        state: Option<String>,
+        older_than: Option<String>,
+        queue: Option<String>,
+        delete_files: bool,
+    ) -> anyhow::Result<(usize, Vec<anyhow::Error>)> {
+        let state = state.as_deref().map(State::parse).transpose()?;
+        let finished_before = match older_than {
+            Some(age) => {
+                let before = Utc::now().checked_sub_signed(Self::parse_age(age.as_str())?);
+                Some(before.ok_or_else(|| anyhow::anyhow!("Invalid age '{}', it goes back before the earliest date", age))?)
+            }
+            None => None,
+        };
+
+        let purged: Vec<usize> = self
+            .done_list
+            .iter()
+            .map(|obj| obj.lock_or_recover())
+            .filter(|obj| {
+                state.is_none_or(|state| state == obj.state)
+                    && queue.as_ref().is_none_or(|queue| *queue == obj.queue)
+                    && finished_before.is_none_or(|before| Self::finished_before(obj, before))
+            })
+            .map(|obj| obj.id)
+            .collect();
+
+        let mut count = 0;
+        let mut errors = vec![];
+        for id in purged {
+            if self.is_dependency(id) {
+                continue;
+            }
+            match self.remove(id, delete_files) {
+                Ok(()) => count += 1,
+                Err(e) => errors.push(e),
+            }
+        }
+
+        Ok((count, errors))
+    }
+
+    // a policy whose days can't be subtracted from now is refused, it would fail on every loop
+    pub fn set_retention(&mut self, policy: RetentionPolicy) -> anyhow::Result<()> {
+        policy.finished_before(Utc::now())?;
+        self.retention = policy;
+
+        Ok(())
+    }
+
+    // drop the finished jobs beyond the last keep_last or older than keep_days
+    fn apply_retention(&mut self) {
+        let mut expired = vec![];
+
+        if let Some(keep_last) = self.retention.keep_last {
+            let extra = self.done_list.len().saturating_sub(keep_last);
+            expired.extend(self.done_list.iter().take(extra).map(|obj| obj.lock_or_recover().id));
+        }
+        if let Ok(Some(before)) = self.retention.finished_before(Utc::now()) {
+            expired.extend(
+                self.done_list
+                    .iter()
+                    .map(|obj| obj.lock_or_recover())
+                    .filter(|obj| Self::finished_before(obj, before))
+                    .map(|obj| obj.id),
+            );
+        }
+
+        for id in expired {
+            if !self.is_dependency(id) {
+                let _ = self.remove(id, false);
+            }
+        }
+    }
+
+    fn finished_before(obj: &DownloadObject, before: DateTime<Utc>) -> bool {
+        obj.finished_at.is_some_and(|finished_at| finished_at < before)
+    }
+
+    // a waiting job would wait forever for a dependency that is dropped from the history
+    fn is_dependency(&self, id: usize) -> bool {
+        self.waiting_list
+            .iter()
+            .any(|obj| obj.lock_or_recover().after.contains(&id))
+    }
+
+    // a failed, paused or cancelled job leaves its partial file, it is deleted too
+    fn delete_file(obj: &DownloadObject) -> anyhow::Result<()> {
+        if !obj.wrote_file() {
+            return Ok(());
+        }
+
+        let path = format!("{}/{}", obj.download_path, obj.name);
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(anyhow::anyhow!("Failed to delete {}: {}", path, e))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // an age is a number over 0 followed by its unit, d for days, h for hours, m for minutes or s for seconds
+    // an age too large for a duration is invalid as well
+    fn parse_age(age: &str) -> anyhow::Result<chrono::Duration> {
+        let invalid = || anyhow::anyhow!("Invalid age '{}', use a number over 0 followed by d, h, m or s", age);
+        let unit = age.chars().last().ok_or_else(invalid)?;
+        let number = age
+            .strip_suffix(unit)
+            .and_then(|number| number.parse::<i64>().ok())
+            .filter(|number| *number > 0)
+            .ok_or_else(invalid)?;
+
+        let age = match unit {
+            'd' => chrono::TimeDelta::try_days(number),
+            'h' => chrono::TimeDelta::try_hours(number),
+            'm' => chrono::TimeDelta::try_minutes(number),
+            's' => chrono::TimeDelta::try_seconds(number),
+            _ => None,
+        };
+        age.ok_or_else(invalid)
+    }
+
+    // the options of a job that finished with the file on the disk
+    fn finished_options(&self, id: usize) -> anyhow::Result<AddOptions> {
+        let obj = self
+            .done_list
+            .iter()
+            .find(|obj| obj.lock_or_recover().id == id)
+            .ok_or_else(|| anyhow::anyhow!("There is no finished job with the id {}", id))?;
+        let obj = obj.lock_or_recover();
+
+        match obj.state {
+            State::Done | State::NotModified => Ok(obj.options.clone()),
+            state => Err(anyhow::anyhow!("The job {} is {}, only completed jobs can be downloaded again", id, state)),
+        }
+    }
+
+    pub fn create_queue(
+        &mut self,
+        name: String,
+        max_jobs: usize,
+        download_path: Option<String>,
+        speed_limit: Option<u64>,
+    ) -> anyhow::Result<()> {
+        if self.queues.contains_key(&name) {
+            return Err(anyhow::anyhow!("The queue {} already exists", name));
+        }
+
+        let queue = DownloadQueue::new(name.clone(), max_jobs, download_path, speed_limit);
+        self.queues.insert(name, queue);
+
+        Ok(())
+    }
+
+    // the jobs in the done list keep the name of their deleted queue
+    pub fn delete_queue(&mut self, name: String) -> anyhow::Result<()> {
+        if name == DEFAULT_QUEUE {
+            return Err(anyhow::anyhow!("The default queue can't be deleted"));
+        }
+        if !self.queues.contains_key(&name) {
+            return Err(anyhow::anyhow!("There is no queue with the name {}", name));
+        }
+
+        let has_jobs = self
+            .waiting_list
+            .iter()
+            .chain(self.active_list.values())
+            .any(|obj| obj.lock_or_recover().queue == name);
+        if has_jobs {
+            return Err(anyhow::anyhow!("The queue {} still has waiting or active jobs", name));
+        }
+
+        self.queues.remove(&name);
+
+        Ok(())
+    }
+
+    // set the command that runs once all jobs of the group are done successfully
+    pub fn set_group_hook(&mut self, name: String, hook: String) {
+        self.groups
+            .entry(name.clone())
+            .or_insert_with(|| DownloadGroup::new(name))
+            .hook = Some(hook);
+    }
+
+    // the validators of the newest download of the url that finished with the file on the disk
+    fn last_validators_of(&self, url: &str) -> Option<Validators> {
+        self.done_list.iter().rev().find_map(|obj| {
+            let obj = obj.lock_or_recover();
+            match obj.state {
+                State::Done | State::NotModified if obj.url == url => Some(obj.validators.clone()),
+                _ => None,
+            }
+        })
+    }
+
+    fn new_download_object(
         &mut self,
         custom_name: Option<String>,
         download_path: String,
         url: String,
-    ) -> anyhow::Result<()> {
+    ) -> DownloadObject {
 
-        //set the name by the custom name the user set
+        //set the name by the custom name the user set, it keeps the extension of the url if there is one
         let name = {
-            let mut fname = url.rsplit_once("/").unwrap().1.to_string();
-            if let Some(cn) = custom_name {
-                fname = format!("{}.{}", cn, fname.rsplit_once(".").unwrap().1);
+            let fname = url.rsplit_once("/").map_or(url.as_str(), |(_, fname)| fname);
+            let fname = if fname.is_empty() { "index.html" } else { fname };
+            match (custom_name, fname.rsplit_once(".")) {
+                (Some(cn), Some((_, extension))) => format!("{}.{}", cn, extension),
+                (Some(cn), None) => cn,
+                (None, _) => fname.to_string(),
             }
-            fname
         };
 
-        self.waiting_list
-            .push_back(Arc::new(Mutex::new(DownloadObject {
-                name,
-                url,
-                state: State::Waiting,
-                total_size: None,
-                download_path,
-                resulted_err: None,
-            })));
+        self.next_id += 1;
+        let now = Utc::now();
+
+        DownloadObject {
+            id: self.next_id,
+            name,
+            url,
+            state: State::Waiting,
+            download_path,
+            created_at: now,
+            queued_at: now,
+            ..Default::default()
+        }
+    }
+
+    // register a url to be downloaded every time the cron expression fires, the function returns the id of the schedule
+    pub fn schedule(
+        &mut self,
+        url: String,
+        cron: String,
+        download_path: String,
+        name_template: Option<String>,
+    ) -> anyhow::Result<usize> {
+        let name_template = name_template.unwrap_or_else(|| DEFAULT_NAME_TEMPLATE.to_string());
+        let schedule = DownloadSchedule::new(self.next_schedule_id + 1, url, cron, download_path, name_template)?;
+        self.next_schedule_id = schedule.id;
+        self.schedules.push(schedule);
+
+        Ok(self.next_schedule_id)
+    }
+
+    // stop a schedule from firing, the downloads it added stay in their lists
+    pub fn unschedule(&mut self, id: usize) -> anyhow::Result<()> {
+        let index = self
+            .schedules
+            .iter()
+            .position(|schedule| schedule.id == id)
+            .ok_or_else(|| anyhow::anyhow!("There is no schedule with the id {}", id))?;
+        self.schedules.remove(index);
 
         Ok(())
     }
 
-    // return a download object if the running threads less than the max_jobs
+    // add a download object to the waiting list for each schedule that is due
+    // the last validators of the schedule are passed along so an unchanged file is skipped
+    fn fire_schedules(&mut self) {
+        if self.shutdown_deadline.is_some() {
+            return;
+        }
+
+        let now = Utc::now();
+        let mut due = vec![];
+        for schedule in self.schedules.iter_mut() {
+            if schedule.fire(now) {
+                due.push(schedule.id);
+            }
+        }
+
+        for id in due {
+            let Some(schedule) = self.schedules.iter().find(|schedule| schedule.id == id) else {
+                continue;
+            };
+            let (name, download_path, url) = (
+                schedule.render_name(now),
+                schedule.download_path.clone(),
+                schedule.url.clone(),
+            );
+            let validators = schedule.validators.clone();
+            let options = AddOptions {
+                url: url.clone(),
+                custom_name: Some(name.clone()),
+                custom_download_path: Some(download_path.clone()),
+                ..Default::default()
+            };
+
+            let mut obj = self.new_download_object(Some(name), download_path, url);
+            obj.options = options;
+            obj.only_if_changed = validators;
+            obj.schedule = Some(id);
+            obj.queue = DEFAULT_QUEUE.to_string();
+
+            self.bus.publish(&obj, JobEventKind::Added { url: obj.url.clone() });
+            self.waiting_list.push_back(Arc::new(Mutex::new(obj)));
+        }
+    }
+
+    // return a download object if the running tasks of its queue less than the max_jobs of the queue
+    // the first waiting download object whose dependencies are all done is chosen
     fn next(&mut self) -> Option<Arc<Mutex<DownloadObject>>> {
-        if self.active_list.len() < self.max_jobs {
-            self.waiting_list.pop_front()
-        } else {
-            None
+        let index = self.waiting_list.iter().position(|obj| {
+            let obj = obj.lock_or_recover();
+            obj.state != State::Paused
+                && self.has_free_slot(obj.queue.as_str())
+                && obj.after.iter().all(|id| {
+                    matches!(self.finished_state(*id), Some(State::Done | State::NotModified))
+                })
+        })?;
+
+        self.waiting_list.remove(index)
+    }
+
+    fn has_free_slot(&self, queue: &str) -> bool {
+        let max_jobs = match self.queues.get(queue) {
+            Some(q) => q.max_jobs,
+            None => return false,
+        };
+        let active = self
+            .active_list
+            .values()
+            .filter(|obj| obj.lock_or_recover().queue == queue)
+            .count();
+
+        active < max_jobs
+    }
+
+    // the state of a job if it is in the done list
+    fn finished_state(&self, id: usize) -> Option<State> {
+        self.done_list
+            .iter()
+            .map(|obj| obj.lock_or_recover())
+            .find(|obj| obj.id == id)
+            .map(|obj| obj.state)
+    }
+
+    // move the waiting download objects that depend on a failed job to the done list
+    // it loops until nothing moves, because a blocked job blocks its own dependents as well
+    fn block_dependents_of_failed(&mut self) {
+        loop {
+            let mut blocked = None;
+            for (index, obj) in self.waiting_list.iter().enumerate() {
+                let obj = obj.lock_or_recover();
+                let failed = obj.after.iter().find(|id| {
+                    matches!(self.finished_state(**id), Some(State::Failed | State::Blocked))
+                });
+                if let Some(failed) = failed {
+                    blocked = Some((index, *failed));
+                    break;
+                }
+            }
+
+            let (index, failed) = match blocked {
+                Some(blocked) => blocked,
+                None => return,
+            };
+
+            let obj = self.waiting_list.remove(index).unwrap();
+            {
+                let mut obj = obj.lock_or_recover();
+                obj.state = State::Blocked;
+                obj.finished_at = Some(Utc::now());
+                obj.resulted_err = Some(DownloadError::Blocked { after: failed });
+                self.bus.publish(&obj, JobEventKind::Blocked { after: failed });
+            }
+            self.done_list.push(obj);
         }
     }
 
-    // call a download executor to create a new thread and move a download object to the active list
-    fn exec_in_thread(&mut self, id: usize, data: Arc<Mutex<DownloadObject>>) {
-        let de = DownloadExecutor::new(id, Arc::clone(&data));
+    // run the hooks of the groups whose download objects are all done successfully
+    fn fire_group_hooks(&mut self) {
+        for group in self.groups.values_mut() {
+            if group.fired {
+                continue;
+            }
+
+            let mut jobs = self
+                .waiting_list
+                .iter()
+                .chain(self.active_list.values())
+                .chain(self.done_list.iter())
+                .map(|obj| obj.lock_or_recover())
+                .filter(|obj| obj.group.as_deref() == Some(group.name.as_str()))
+                .peekable();
+
+            if jobs.peek().is_none() {
+                continue;
+            }
+
+            if jobs.all(|obj| matches!(obj.state, State::Done | State::NotModified)) {
+                group.fire();
+            }
+        }
+    }
+
+    // call a download executor to create a new task and move a download object to the active list
+    fn exec_in_task(&mut self, data: Arc<Mutex<DownloadObject>>, events: Sender<SchedulerEvent>) {
+        let (id, queue, key) = {
+            let obj = data.lock_or_recover();
+            let key = ClientKey {
+                proxy: obj.options.proxy.clone(),
+                insecure: obj.options.insecure,
+            };
+            (obj.id, obj.queue.clone(), key)
+        };
+
+        let client = match self.client_factory.client_for(key) {
+            Ok(client) => client,
+            Err(e) => {
+                {
+                    let mut obj = data.lock_or_recover();
+                    obj.state = State::Failed;
+                    obj.resulted_err = Some(DownloadError::invalid_request(e));
+                    obj.finished_at = Some(Utc::now());
+                    self.bus.publish(&obj, JobEventKind::Failed { error: obj.resulted_err.clone() });
+                }
+                self.done_list.push(data);
+                return;
+            }
+        };
+
+        // the job is active before its executor runs, so the executor's final state isn't overwritten
+        {
+            let mut obj = data.lock_or_recover();
+            let now = Utc::now();
+            obj.state = State::Active;
+            obj.started_at = Some(now);
+            // the executor corrects the offset if the server sends the whole file again
+            let resumed_from = if obj.resume { obj.downloaded_size } else { 0 };
+            let attempt = Attempt::new(obj.queued_at, now, resumed_from);
+            obj.attempts.push(attempt);
+            self.bus.publish(&obj, JobEventKind::Started { attempt: obj.attempts.len() });
+        }
+
+        let limiter = self.queues.get(&queue).and_then(|q| q.limiter.clone());
+        let stall_timeout = self.client_factory.stall_timeout();
+        let de = DownloadExecutor::new(id, Arc::clone(&data), limiter, client, stall_timeout, events);
         self.active_list.insert(id, Arc::clone(&data));
-        data.lock().unwrap().state = State::Active;
 
         self.download_executor.insert(id, de);
     }
 
+    // start a task for every waiting download object that has a free slot in its queue
+    fn fill_free_slots(&mut self, events: &Sender<SchedulerEvent>) {
+        if self.shutdown_deadline.is_some() {
+            return;
+        }
+
+        while let Some(obj) = self.next() {
+            self.exec_in_task(obj, events.clone());
+        }
+    }
+
 
-    // if a thread finished the try_recv() will return the id of the thread
-    // the id is sent when we call the download executor
-    fn check_finished_threads(&mut self) {
-        let mut to_be_removed = vec![];
-        for (_, de) in &self.download_executor {
-            if let Ok(id) = de.sched_rx.try_recv() {
-                let down_done = self.active_list.remove(&id).unwrap();
-                let _ = to_be_removed.push(id);
-                self.done_list.push(down_done);
+    // the executor of the job sent that its task finished
+    // the download object moves to the done list either it is Done or it Failed
+    async fn finish(&mut self, id: usize) {
+        let down_done = match self.active_list.remove(&id) {
+            Some(obj) => obj,
+            None => return,
+        };
+
+        // a schedule compares its next download against the last one that finished
+        {
+            let mut obj = down_done.lock_or_recover();
+            let now = Utc::now();
+            obj.finished_at = Some(now);
+            obj.close_attempt(now);
+
+            // the schedule may have been removed since it added the job
+            if let (Some(id), State::Done | State::NotModified) = (obj.schedule, &obj.state) {
+                if let Some(schedule) = self.schedules.iter_mut().find(|schedule| schedule.id == id) {
+                    schedule.validators = Some(obj.validators.clone());
+                }
             }
+
+            let kind = match obj.state {
+                State::Done => JobEventKind::Done { final_size: obj.final_size },
+                State::NotModified => JobEventKind::NotModified,
+                _ => JobEventKind::Failed { error: obj.resulted_err.clone() },
+            };
+            self.bus.publish(&obj, kind);
+        }
+
+        self.done_list.push(down_done);
+
+        if let Some(de) = self.download_executor.remove(&id) {
+            de.join().await;
         }
+    }
 
-        for index in to_be_removed {
-            self.download_executor.remove(&index);
+    // stop admitting jobs and stop the scheduler once the active jobs finished or the timeout passed
+    // if pause is set the active jobs are paused right away, a second shutdown can only move the deadline closer
+    pub fn shutdown(&mut self, timeout: Option<u64>, pause: bool) -> String {
+        let timeout = if pause { 0 } else { timeout.unwrap_or(SHUTDOWN_TIMEOUT) };
+        let deadline = Utc::now() + chrono::Duration::seconds(timeout as i64);
+        let deadline = self.shutdown_deadline.map_or(deadline, |previous| previous.min(deadline));
+        self.shutdown_deadline = Some(deadline);
+
+        let active = self.active_list.len();
+        if active == 0 {
+            String::from("Shutting down, there are no active downloads")
+        } else if pause {
+            format!("Shutting down, {} active downloads are paused", active)
+        } else {
+            format!(
+                "Shutting down, waiting until {} for {} active downloads",
+                deadline.to_rfc3339(),
+                active
+            )
         }
     }
 
-    pub fn run(sched: Self, thread_rx: Receiver<Manager>, thread_tx: Sender<Vec<String>>) {
-        let mut id_counter = 0_usize;
+    fn shutdown_is_due(&self) -> bool {
+        self.shutdown_deadline
+            .is_some_and(|deadline| self.active_list.is_empty() || Utc::now() >= deadline)
+    }
+
+    fn adds_jobs(command: &ManagerCommands) -> bool {
+        matches!(
+            command,
+            ManagerCommands::Add { .. }
+                | ManagerCommands::Retry { .. }
+                | ManagerCommands::RetryAllFailed
+                | ManagerCommands::Redownload { .. }
+                | ManagerCommands::Clone { .. }
+                | ManagerCommands::Schedule { .. }
+        )
+    }
+
+    // pause the active jobs and save the lists, the scheduler doesn't run after it
+    // the jobs paused by the shutdown wait to be resumed by the next start, unlike the ones a user paused
+    async fn stop(&mut self) -> anyhow::Result<()> {
+        let mut ids: Vec<usize> = self.active_list.keys().copied().collect();
+        ids.sort_unstable();
+
+        // the paused jobs go back to the front of the waiting list in the order of their ids
+        for id in ids.into_iter().rev() {
+            if let Some(obj) = self.pause_active(id).await {
+                obj.lock_or_recover().state = State::Waiting;
+            }
+        }
+
+        self.save_state()
+    }
+
+    // stop the executor of an active job, the job keeps its partial file and goes to the front of the waiting list
+    // returns None if the executor finished before it was stopped, then the job moves to the done list
+    async fn pause_active(&mut self, id: usize) -> Option<Arc<Mutex<DownloadObject>>> {
+        if let Some(de) = self.download_executor.remove(&id) {
+            de.abort().await;
+        }
+
+        let obj = Arc::clone(self.active_list.get(&id)?);
+        let paused = {
+            let mut obj_ptr = obj.lock_or_recover();
+            if obj_ptr.state == State::Active {
+                obj_ptr.state = State::Paused;
+                // a job stopped before its executor opened its file starts over with a file of its own
+                obj_ptr.resume = obj_ptr.file_opened;
+                obj_ptr.close_attempt(Utc::now());
+                self.bus.publish(&obj_ptr, JobEventKind::Paused);
+                true
+            } else {
+                false
+            }
+        };
+
+        // the event of its end is still in the channel, the scheduler ignores it after this
+        if !paused {
+            self.finish(id).await;
+            return None;
+        }
+
+        self.active_list.remove(&id);
+        self.waiting_list.push_front(Arc::clone(&obj));
+
+        Some(obj)
+    }
+
+    fn save_state(&self) -> anyhow::Result<()> {
+        let objects = |list: &mut dyn Iterator<Item = &Arc<Mutex<DownloadObject>>>| {
+            list.map(|obj| obj.lock_or_recover().clone()).collect::<Vec<_>>()
+        };
+
+        let state = SavedState {
+            next_id: self.next_id,
+            next_schedule_id: self.next_schedule_id,
+            queues: self
+                .queues
+                .values()
+                .map(|q| SavedQueue {
+                    name: q.name.clone(),
+                    max_jobs: q.max_jobs,
+                    download_path: q.download_path.clone(),
+                    speed_limit: q.speed_limit,
+                })
+                .collect(),
+            groups: self
+                .groups
+                .values()
+                .map(|group| SavedGroup {
+                    name: group.name.clone(),
+                    hook: group.hook.clone(),
+                    fired: group.fired,
+                })
+                .collect(),
+            schedules: self
+                .schedules
+                .iter()
+                .map(|schedule| SavedSchedule {
+                    id: Some(schedule.id),
+                    url: schedule.url.clone(),
+                    cron: schedule.cron.clone(),
+                    download_path: schedule.download_path.clone(),
+                    name_template: schedule.name_template.clone(),
+                    validators: schedule.validators.clone(),
+                })
+                .collect(),
+            waiting: objects(&mut self.waiting_list.iter()),
+            done: objects(&mut self.done_list.iter()),
+        };
+
+        state.save(self.download_path.as_str())
+    }
+
+    // load the lists the last shutdown saved in the download path, it returns the number of restored jobs
+    pub fn restore_state(&mut self) -> anyhow::Result<usize> {
+        let state = match SavedState::load(self.download_path.as_str())? {
+            Some(state) => state,
+            None => return Ok(0),
+        };
+
+        for q in state.queues {
+            if q.name != DEFAULT_QUEUE {
+                let queue = DownloadQueue::new(q.name.clone(), q.max_jobs, q.download_path, q.speed_limit);
+                self.queues.insert(q.name, queue);
+            }
+        }
+        for group in state.groups {
+            let mut restored = DownloadGroup::new(group.name.clone());
+            restored.hook = group.hook;
+            restored.fired = group.fired;
+            self.groups.insert(group.name, restored);
+        }
+        self.next_schedule_id = self.next_schedule_id.max(state.next_schedule_id);
+        for schedule in state.schedules {
+            let id = schedule.id.unwrap_or(self.next_schedule_id + 1);
+            self.next_schedule_id = self.next_schedule_id.max(id);
+            let mut restored = DownloadSchedule::new(
+                id,
+                schedule.url,
+                schedule.cron,
+                schedule.download_path,
+                schedule.name_template,
+            )?;
+            restored.validators = schedule.validators;
+            self.schedules.push(restored);
+        }
+
+        let count = state.waiting.len() + state.done.len();
+        for mut obj in state.waiting {
+            // a job of a queue that no longer exists would never start
+            if !self.queues.contains_key(&obj.queue) {
+                obj.queue = DEFAULT_QUEUE.to_string();
+            }
+            self.waiting_list.push_back(Arc::new(Mutex::new(obj)));
+        }
+        for obj in state.done {
+            self.done_list.push(Arc::new(Mutex::new(obj)));
+        }
+        self.next_id = self.next_id.max(state.next_id);
+
+        Ok(count)
+    }
+
+    // the restored jobs are only in memory from now on, they are saved again at the next shutdown
+    pub fn consume_saved_state(&self) -> anyhow::Result<()> {
+        SavedState::remove(self.download_path.as_str())
+    }
+
+    // an executor that stopped without flagging the scheduler leaves its job active forever
+    // so the job fails and its slot is freed
+    async fn reap_dead_executors(&mut self) {
+        let dead: Vec<usize> = self
+            .download_executor
+            .iter()
+            .filter(|(_, de)| de.is_dead())
+            .map(|(id, _)| *id)
+            .filter(|id| {
+                self.active_list
+                    .get(id)
+                    .is_some_and(|obj| obj.lock_or_recover().state == State::Active)
+            })
+            .collect();
+
+        for id in dead {
+            if let Some(obj) = self.active_list.get(&id) {
+                let mut obj = obj.lock_or_recover();
+                obj.state = State::Failed;
+                obj.resulted_err = Some(DownloadError::internal("The executor of the job stopped without finishing"));
+            }
+            self.finish(id).await;
+        }
+    }
+
+    fn progress(&mut self, id: usize, downloaded: u64) {
+        if let Some(obj) = self.active_list.get(&id) {
+            obj.lock_or_recover().downloaded_size = downloaded;
+        }
+    }
+
+    // the progress of every active job, published on every tick
+    // the state of the lists the metrics take their gauges from
+    fn snapshot(&self) -> Snapshot {
+        let now = Utc::now();
+        Snapshot {
+            states: self.all_lists().map(|obj| obj.lock_or_recover().state).collect(),
+            active: self
+                .active_list
+                .values()
+                .map(|obj| {
+                    let obj = obj.lock_or_recover();
+                    let resumed_from = obj.attempts.last().map_or(0, |attempt| attempt.resumed_from);
+                    ActiveJob {
+                        id: obj.id,
+                        downloaded: obj.downloaded_size.saturating_sub(resumed_from),
+                        throughput: obj.live_throughput(now),
+                    }
+                })
+                .collect(),
+            executors: self.download_executor.len(),
+        }
+    }
+
+    fn publish_progress(&self) {
+        let now = Utc::now();
+        for obj in self.active_list.values() {
+            let obj = obj.lock_or_recover();
+            let throughput = obj.live_throughput(now);
+
+            self.bus.publish(
+                &obj,
+                JobEventKind::Progress {
+                    downloaded: obj.downloaded_size,
+                    total_size: obj.total_size,
+                    throughput,
+                },
+            );
+        }
+    }
+
+    // the scheduler reacts to the events of one channel: the commands of the server,
+    // the progress and the end of the executors and the ticks of the timer
+    // the timer is only needed for the schedules and the retention policy, the rest starts the jobs right away
+    pub fn run(
+        sched: Self,
+        mut events_rx: Receiver<SchedulerEvent>,
+        events_tx: Sender<SchedulerEvent>,
+    ) -> JoinHandle<()> {
         let mut sched = sched;
 
-        // this is the background thread that generates another threads for each download object
-        std::thread::spawn(move || loop {
-            // sched.debug_all();
-
-            // if we can run a new download object => send it to a thread to handle
-            if let Some(obj) = sched.next() {
-                sched.exec_in_thread(id_counter, obj);
-            }
-            
-            // loop on the try_recv() for each thread
-            sched.check_finished_threads();
-            id_counter += 1;
-
-
-            // receives from the server
-            if let Ok(mngr) = thread_rx.try_recv() {
-                let mut list = vec![];
-
-                // handling the commmands
-                match mngr.subcommands {
-                    ManagerCommands::Add {
-                        url,
-                        custom_name,
-                        custom_download_path,
-                    } => {
-
-                        let down_path = if let Some(custom_download_path) = custom_download_path {
-                            custom_download_path
-                        } else {
-                            sched.download_path.clone()
-                        };
-                        let ret = sched.add(custom_name, down_path, url);
-                        if let Err(e) = ret {
-                            list.push(e.to_string());
-                        } else {
-                            list.push(String::from("File added"));
-                        }
+        let ticks = events_tx.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                if ticks.send(SchedulerEvent::Tick).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        // this is the background task that generates another tasks for each download object
+        tokio::spawn(async move {
+            while let Some(event) = events_rx.recv().await {
+                match event {
+                    SchedulerEvent::Command(mngr, reply) => {
+                        // a panic while handling a command fails only this command
+                        let reply_body = catch_panic(sched.handle(*mngr))
+                            .await
+                            .unwrap_or_else(|panic| Err(ApiError::internal(panic)));
+
+                        // send the reply, the server may have stopped waiting for it
+                        let _ = reply.send(reply_body);
                     }
-                    ManagerCommands::List { subcommands } => match subcommands {
-                        crate::commands::ListCommands::All => {
-                            list.push(sched.stringify_waiting_list());
-                            list.push(sched.stringify_active_list());
-                            list.push(sched.stringify_done_list());
-                        }
-                        crate::commands::ListCommands::Active => {
-                            list.push(sched.stringify_active_list());
-                        }
-                        crate::commands::ListCommands::Done => {
-                            list.push(sched.stringify_done_list());
-                        }
-                    },
-                    ManagerCommands::Info { filename } => {
-                        if let Some(info) = sched.get_info(filename) {
-                            list.push(info);
-                        } else {
-                            list.push(String::from("This file is not found!!"));
+                    SchedulerEvent::Job(request, reply) => {
+                        let reply_body = catch_panic(sched.handle_job(request))
+                            .await
+                            .unwrap_or_else(|panic| Err(ApiError::internal(panic)));
+
+                        let _ = reply.send(reply_body);
+                    }
+                    SchedulerEvent::Progress { id, downloaded } => {
+                        // the progress doesn't change what can run
+                        sched.progress(id, downloaded);
+                        continue;
+                    }
+                    SchedulerEvent::Finished(id) => sched.finish(id).await,
+                    SchedulerEvent::Subscribe(reply) => {
+                        let _ = reply.send(sched.bus.subscribe());
+                        continue;
+                    }
+                    SchedulerEvent::Metrics(reply) => {
+                        let _ = reply.send(sched.bus.metrics().render(&sched.snapshot()));
+                        continue;
+                    }
+                    SchedulerEvent::Tick => {
+                        // a panic in a step of the tick skips only this step, the next tick runs it again
+                        let steps = [
+                            catch_panic(async { sched.publish_progress() }).await,
+                            // enqueue the downloads of the due schedules
+                            catch_panic(async { sched.fire_schedules() }).await,
+                            catch_panic(async { sched.apply_retention() }).await,
+                            catch_panic(sched.reap_dead_executors()).await,
+                        ];
+                        for panic in steps.into_iter().filter_map(Result::err) {
+                            eprintln!("Error: a step of the tick failed: {}", panic);
                         }
                     }
-                    _ => (),
                 }
 
-                // send the result
-                let _ = thread_tx.send(list);
-            } else {
-                // leave some space to the other threads
-                thread::sleep(Duration::from_secs(1));
+                // the jobs that depend on a failed job will never start
+                sched.block_dependents_of_failed();
+                sched.fire_group_hooks();
+
+                // if we can run new download objects => send them to tasks to handle
+                sched.fill_free_slots(&events_tx);
+
+                // the loop ends once the shutdown is due, the server stops when it sees the task ended
+                if sched.shutdown_is_due() {
+                    if let Err(e) = sched.stop().await {
+                        eprintln!("Error: {:?}", e);
+                    }
+                    break;
+                }
             }
-        });
+        })
+    }
+
+    // handle a request of the REST resources of the server
+    async fn handle_job(&mut self, request: JobRequest) -> Reply {
+        match request {
+            JobRequest::Add(options) => {
+                if self.shutdown_deadline.is_some() {
+                    return Err(ApiError::shutting_down());
+                }
+                let id = self.add(options).map_err(ApiError::invalid)?;
+                let obj = self.require(id)?;
+                let body = ResponseBody::job(&obj.lock_or_recover());
+                Ok(body)
+            }
+            JobRequest::List { state } => {
+                let jobs = self
+                    .all_lists()
+                    .map(|obj| JobView::from(&*obj.lock_or_recover()))
+                    .filter(|job| state.is_none_or(|state| state == job.state))
+                    .collect();
+                Ok(ResponseBody::jobs(jobs))
+            }
+            JobRequest::Get(id) => {
+                let obj = self.require(id)?;
+                let body = ResponseBody::job(&obj.lock_or_recover());
+                Ok(body)
+            }
+            JobRequest::Delete { id, delete_file } => {
+                self.require(id)?;
+                // an active job is stopped first, it is dropped either way
+                if self.active_list.contains_key(&id) {
+                    self.pause_active(id).await;
+                }
+                self.remove(id, delete_file).map_err(ApiError::conflict)?;
+                Ok(ResponseBody::message(format!("Job {} removed", id)))
+            }
+            JobRequest::Cancel { id, delete_file } => {
+                let obj = self.require(id)?;
+                self.cancel(id, delete_file).await.map_err(ApiError::conflict)?;
+                let body = ResponseBody::job(&obj.lock_or_recover());
+                Ok(body)
+            }
+            JobRequest::Pause(id) => {
+                let obj = self.require(id)?;
+                let state = obj.lock_or_recover().state;
+                match state {
+                    State::Active => {
+                        self.pause_active(id).await;
+                    }
+                    State::Waiting => {
+                        let mut obj_ptr = obj.lock_or_recover();
+                        obj_ptr.state = State::Paused;
+                        self.bus.publish(&obj_ptr, JobEventKind::Paused);
+                    }
+                    State::Paused => (),
+                    _ => return Err(ApiError::conflict(format!("The job {} is {}, it can't be paused", id, state))),
+                }
+                let body = ResponseBody::job(&obj.lock_or_recover());
+                Ok(body)
+            }
+            JobRequest::Resume(id) => {
+                let obj = self.require(id)?;
+                let mut obj_ptr = obj.lock_or_recover();
+                if obj_ptr.state != State::Paused {
+                    return Err(ApiError::conflict(format!(
+                        "The job {} is {}, only paused jobs can be resumed",
+                        id, obj_ptr.state
+                    )));
+                }
+                obj_ptr.state = State::Waiting;
+                obj_ptr.queued_at = Utc::now();
+                obj_ptr.started_at = None;
+                self.bus.publish(&obj_ptr, JobEventKind::Resumed);
+                Ok(ResponseBody::job(&obj_ptr))
+            }
+            JobRequest::Retry(id) => {
+                let obj = self.require(id)?;
+                self.retry(id).map_err(ApiError::conflict)?;
+                let body = ResponseBody::job(&obj.lock_or_recover());
+                Ok(body)
+            }
+            JobRequest::Move { id, position } => {
+                let obj = self.require(id)?;
+                self.move_waiting(id, position).map_err(ApiError::conflict)?;
+                let body = ResponseBody::job(&obj.lock_or_recover());
+                Ok(body)
+            }
+        }
+    }
+
+    // handle a command of the server, the reply is the body of the response or an error with its code
+    // the commands on a job check that the job exists first, so their other errors are conflicts
+    async fn handle(&mut self, mngr: Manager) -> Reply {
+        // while shutting down only the commands that don't add jobs are served
+        if self.shutdown_deadline.is_some() && Self::adds_jobs(&mngr.subcommands) {
+            return Err(ApiError::shutting_down());
+        }
+
+        match mngr.subcommands {
+            ManagerCommands::Add { options } => {
+                let id = self.add(options).map_err(ApiError::invalid)?;
+                Ok(ResponseBody::Added { id })
+            }
+            ManagerCommands::Retry { id } => {
+                self.require(id)?;
+                self.retry(id).map_err(ApiError::conflict)?;
+                Ok(ResponseBody::message(format!("Job {} moved to the waiting list", id)))
+            }
+            ManagerCommands::RetryAllFailed => {
+                let count = self.retry_all_failed();
+                Ok(ResponseBody::Count {
+                    count,
+                    message: format!("{} failed jobs moved to the waiting list", count),
+                })
+            }
+            ManagerCommands::Redownload { id } => {
+                self.require(id)?;
+                let id = self.redownload(id).map_err(ApiError::conflict)?;
+                Ok(ResponseBody::Added { id })
+            }
+            ManagerCommands::Clone { id, overrides } => {
+                self.require(id)?;
+                let id = self.clone_job(id, overrides).map_err(ApiError::invalid)?;
+                Ok(ResponseBody::Added { id })
+            }
+            ManagerCommands::Remove { id, delete_file } => {
+                self.require(id)?;
+                self.remove(id, delete_file).map_err(ApiError::conflict)?;
+                Ok(ResponseBody::message(format!("Job {} removed", id)))
+            }
+            ManagerCommands::Cancel { id, delete_file } => {
+                self.require(id)?;
+                self.cancel(id, delete_file).await.map_err(ApiError::conflict)?;
+                Ok(ResponseBody::message(format!("Job {} cancelled", id)))
+            }
+            ManagerCommands::Purge {
+                state,
+                older_than,
+                queue,
+                delete_files,
+            } => {
+                let (count, errors) = self
+                    .purge(state, older_than, queue, delete_files)
+                    .map_err(ApiError::invalid)?;
+                let message = if errors.is_empty() {
+                    format!("{} jobs purged", count)
+                } else {
+                    let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                    format!("{} jobs purged, {} kept: {}", count, errors.len(), errors.join(", "))
+                };
+                Ok(ResponseBody::Count { count, message })
+            }
+            ManagerCommands::Retention { policy } => {
+                self.set_retention(policy).map_err(ApiError::invalid)?;
+                Ok(ResponseBody::message("Retention policy set"))
+            }
+            ManagerCommands::Group { name, hook } => {
+                self.set_group_hook(name, hook);
+                Ok(ResponseBody::message("Group hook set"))
+            }
+            ManagerCommands::Schedule {
+                url,
+                cron,
+                custom_download_path,
+                name_template,
+            } => {
+                let down_path = custom_download_path.unwrap_or_else(|| self.download_path.clone());
+                let id = self
+                    .schedule(url, cron, down_path, name_template)
+                    .map_err(ApiError::invalid)?;
+                Ok(ResponseBody::message(format!("Schedule {} added", id)))
+            }
+            ManagerCommands::Unschedule { id } => {
+                self.unschedule(id).map_err(ApiError::not_found)?;
+                Ok(ResponseBody::message(format!("Schedule {} removed", id)))
+            }
+            ManagerCommands::List { queue, sort, descending, subcommands } => {
+                let queue = queue.as_deref();
+                let order = sort
+                    .as_deref()
+                    .map(SortKey::parse)
+                    .transpose()
+                    .map_err(ApiError::invalid)?
+                    .map(|key| ListOrder { key, descending });
+                let body = match subcommands {
+                    ListCommands::All => {
+                        let mut jobs = self.waiting_views(queue, order);
+                        jobs.extend(self.active_views(queue, order));
+                        jobs.extend(self.done_views(queue, order));
+                        ResponseBody::jobs(jobs)
+                    }
+                    ListCommands::Active => ResponseBody::jobs(self.active_views(queue, order)),
+                    ListCommands::Done => ResponseBody::jobs(self.done_views(queue, order)),
+                    ListCommands::Schedules => ResponseBody::Schedules {
+                        schedules: self.schedule_views(),
+                    },
+                    ListCommands::Groups => ResponseBody::Groups {
+                        groups: self.group_views(),
+                    },
+                    ListCommands::Queues => ResponseBody::Queues {
+                        queues: self.queue_views(queue),
+                    },
+                };
+                Ok(body)
+            }
+            ManagerCommands::Queue { subcommands } => match subcommands {
+                QueueCommands::Create {
+                    name,
+                    active_downloads,
+                    download_path,
+                    speed_limit,
+                } => {
+                    self.create_queue(name, active_downloads as usize, download_path, speed_limit)
+                        .map_err(ApiError::conflict)?;
+                    Ok(ResponseBody::message("Queue created"))
+                }
+                QueueCommands::Delete { name } => {
+                    if !self.queues.contains_key(&name) {
+                        return Err(ApiError::not_found(format!("There is no queue with the name {}", name)));
+                    }
+                    self.delete_queue(name).map_err(ApiError::conflict)?;
+                    Ok(ResponseBody::message("Queue deleted"))
+                }
+            },
+            ManagerCommands::Shutdown { timeout, pause } => Ok(ResponseBody::message(self.shutdown(timeout, pause))),
+            ManagerCommands::Info { filename } => self
+                .get_info(filename)
+                .ok_or_else(|| ApiError::not_found("This file is not found!!")),
+            // Start is handled by the cli itself
+            _ => Err(ApiError::invalid("This command is not served by the server")),
+        }
     }
 
     // fn debug_all(&self) {
@@ -197,96 +1453,174 @@ impl Scheduler {
 }
 
 
+impl RetentionPolicy {
+    // the jobs that finished before the returned time are dropped, None keeps them whatever their age
+    pub fn finished_before(&self, now: DateTime<Utc>) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let keep_days = match self.keep_days {
+            Some(keep_days) => keep_days,
+            None => return Ok(None),
+        };
+
+        i64::try_from(keep_days)
+            .ok()
+            .and_then(chrono::TimeDelta::try_days)
+            .and_then(|days| now.checked_sub_signed(days))
+            .map(Some)
+            .ok_or_else(|| anyhow::anyhow!("Invalid --keep-days {}, it goes back before the earliest date", keep_days))
+    }
+}
+
 
 // These are helper functions
 impl Scheduler {
-    // the stringify functions construct the files information in a string
+    // the view functions take a snapshot of the download objects for the response, the cli renders them
     // 1 -> waiting
-    fn stringify_waiting_list(&self) -> String {
-        self.stringify_list(1)
+    fn waiting_views(&self, queue: Option<&str>, order: Option<ListOrder>) -> Vec<JobView> {
+        self.list_views(1, queue, order)
     }
     // 2 -> active
-    fn stringify_active_list(&self) -> String {
-        self.stringify_list(2)
+    fn active_views(&self, queue: Option<&str>, order: Option<ListOrder>) -> Vec<JobView> {
+        self.list_views(2, queue, order)
     }
     // 3 -> done
-    fn stringify_done_list(&self) -> String {
-        self.stringify_list(3)
-    }
-    fn stringify_list(&self, list_name: usize) -> String {
-        let stringify_list = |list: &Arc<Mutex<DownloadObject>>| {
-            let list_ptr = list.lock().unwrap();
-            let mut inf = String::new();
-            inf.push_str(list_ptr.name.as_str());
-            inf.push_str("  ( _ / ");
-            inf.push_str(list_ptr.total_size.unwrap().to_string().as_str());
-            inf.push_str(")  ");
-            inf.push_str(list_ptr.state.to_string().as_str());
-            inf.push_str("\r\n");
-            inf
+    fn done_views(&self, queue: Option<&str>, order: Option<ListOrder>) -> Vec<JobView> {
+        self.list_views(3, queue, order)
+    }
+    // if a queue is set only its download objects are listed
+    // if an order is set the download objects are sorted by it, else they keep the order of the list
+    fn list_views(&self, list_name: usize, queue: Option<&str>, order: Option<ListOrder>) -> Vec<JobView> {
+        let mut lists: Vec<&Arc<Mutex<DownloadObject>>> = match list_name {
+            1 => self.waiting_list.iter().collect(),
+            2 => self.active_list.values().collect(),
+            3 => self.done_list.iter().collect(),
+            _ => vec![],
         };
-        let mut ret = String::new();
-        match list_name {
-            1 => {
-                for list in &self.waiting_list {
-                    ret.push_str(stringify_list(list).as_str());
-                }
-            }
-            2 => {
-                for (_, list) in &self.active_list {
-                    ret.push_str(stringify_list(list).as_str());
+
+        if let Some(order) = order {
+            Self::sort_list(&mut lists, order);
+        }
+
+        lists
+            .into_iter()
+            .map(|list| list.lock_or_recover())
+            .filter(|list_ptr| queue.is_none_or(|queue| queue == list_ptr.queue))
+            .map(|list_ptr| JobView::from(&*list_ptr))
+            .collect()
+    }
+
+    // the download objects without a value for the key go last in both directions
+    fn sort_list(lists: &mut Vec<&Arc<Mutex<DownloadObject>>>, order: ListOrder) {
+        let mut keyed: Vec<_> = lists
+            .drain(..)
+            .map(|list| (list.lock_or_recover().sort_value(order.key), list))
+            .collect();
+
+        keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+            (Some(a), Some(b)) if order.descending => b.total_cmp(a),
+            (Some(a), Some(b)) => a.total_cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        });
+
+        lists.extend(keyed.into_iter().map(|(_, list)| list));
+    }
+
+    // every queue with its limits and its download objects
+    fn queue_views(&self, queue: Option<&str>) -> Vec<QueueView> {
+        self.queues
+            .values()
+            .filter(|q| queue.is_none_or(|queue| queue == q.name))
+            .map(|q| {
+                let name = Some(q.name.as_str());
+                let mut jobs = self.waiting_views(name, None);
+                jobs.extend(self.active_views(name, None));
+                jobs.extend(self.done_views(name, None));
+
+                QueueView {
+                    name: q.name.clone(),
+                    max_jobs: q.max_jobs,
+                    download_path: q.download_path.clone().unwrap_or_else(|| self.download_path.clone()),
+                    speed_limit: q.speed_limit,
+                    jobs,
                 }
-            }
-            3 => {
-                for list in &self.done_list {
-                    ret.push_str(stringify_list(list).as_str());
+            })
+            .collect()
+    }
+
+    fn group_views(&self) -> Vec<GroupView> {
+        self.groups
+            .values()
+            .map(|group| {
+                let jobs: Vec<State> = self
+                    .all_lists()
+                    .map(|obj| obj.lock_or_recover())
+                    .filter(|obj| obj.group.as_deref() == Some(group.name.as_str()))
+                    .map(|obj| obj.state)
+                    .collect();
+                let done = jobs
+                    .iter()
+                    .filter(|state| matches!(state, State::Done | State::NotModified))
+                    .count();
+
+                GroupView {
+                    name: group.name.clone(),
+                    hook: group.hook.clone(),
+                    fired: group.fired,
+                    hook_error: group.hook_err.clone(),
+                    jobs: jobs.len(),
+                    done,
                 }
-            }
-            _ => ()
-        }
+            })
+            .collect()
+    }
 
-        ret
+    // the three lists one after the other
+    fn all_lists(&self) -> impl Iterator<Item = &Arc<Mutex<DownloadObject>>> {
+        self.waiting_list
+            .iter()
+            .chain(self.active_list.values())
+            .chain(self.done_list.iter())
     }
 
+    // find a job by its id in the three lists
+    fn find(&self, id: usize) -> Option<Arc<Mutex<DownloadObject>>> {
+        self.all_lists()
+            .find(|obj| obj.lock_or_recover().id == id)
+            .cloned()
+    }
 
-    //get information of a signle file
-    fn get_info(&self, name: String) -> Option<String> {
-        let check_list = |list: &Arc<Mutex<DownloadObject>>| {
-            let list_ptr = list.lock().unwrap();
-            let mut inf = String::new();
-            if list_ptr.name.eq(&name) {
-                inf.push_str(name.as_str());
-                inf.push_str("  ( _ / ");
-                inf.push_str(list_ptr.total_size.unwrap().to_string().as_str());
-                inf.push_str(")  ");
-                inf.push_str(list_ptr.state.to_string().as_str());
-                inf.push_str("\r\n");
-                return Some(inf);
-            }
-
-            None
-        };
+    // the same as find, a missing job is a not found error
+    fn require(&self, id: usize) -> Result<Arc<Mutex<DownloadObject>>, ApiError> {
+        self.find(id)
+            .ok_or_else(|| ApiError::not_found(format!("There is no job with the id {}", id)))
+    }
 
-        for list in &self.done_list {
-            let info = check_list(list);
-            if info.is_some() {
-                return info;
-            }
-        }
-        for list in &self.waiting_list {
-            let info = check_list(list);
-            if info.is_some() {
-                return info;
-            }
-        }
-        for (_, list) in &self.active_list {
-            let info = check_list(list);
-            if info.is_some() {
-                return info;
-            }
-        }
+    fn schedule_views(&self) -> Vec<ScheduleView> {
+        self.schedules
+            .iter()
+            .map(|schedule| ScheduleView {
+                id: schedule.id,
+                url: schedule.url.clone(),
+                cron: schedule.cron.clone(),
+                download_path: schedule.download_path.clone(),
+                name_template: schedule.name_template.clone(),
+                next_fire: schedule.next_fire,
+            })
+            .collect()
+    }
 
-        None
+
+    //get information of a signle file
+    // the done list is checked first, then the waiting and the active ones
+    fn get_info(&self, name: String) -> Option<ResponseBody> {
+        self.done_list
+            .iter()
+            .chain(self.waiting_list.iter())
+            .chain(self.active_list.values())
+            .map(|list| list.lock_or_recover())
+            .find(|list_ptr| list_ptr.name.eq(&name))
+            .map(|list_ptr| ResponseBody::job(&list_ptr))
     }
 
 }
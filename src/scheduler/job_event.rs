@@ -0,0 +1,116 @@
+use super::download_error::DownloadError;
+use super::download_object::DownloadObject;
+use super::metrics::Metrics;
+use super::supervisor::LockOrRecover;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, MutexGuard};
+use tokio::sync::broadcast;
+
+/*
+ * A JobEvent is published by the scheduler every time a job changes, the streams of the server read them
+ *
+ * - The scheduler owns the bus, a subscriber asks the scheduler for a receiver through the events channel
+ *   so all the streams end when the scheduler stops
+ * - Every tick publishes the progress of the active jobs, the executors don't publish themselves
+ * - A subscriber that falls behind by more than the capacity of the bus loses the oldest events
+ * - The metrics are recorded by the bus too, so they see every transition the events show
+ */
+
+// the events a slow subscriber can fall behind before it loses them
+pub const EVENT_BUS_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobEvent {
+    pub id: usize,
+    pub name: String,
+    pub queue: String,
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: JobEventKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JobEventKind {
+    Added { url: String },
+    Started { attempt: usize },
+    // the speed is the average of the running attempt in bytes per second
+    Progress { downloaded: u64, total_size: Option<u64>, throughput: Option<f64> },
+    // a failed job moved back to the waiting list
+    Retrying,
+    Paused,
+    Resumed,
+    // the job moved to this position of the waiting list
+    Moved { position: usize },
+    Done { final_size: Option<u64> },
+    NotModified,
+    Failed { error: Option<DownloadError> },
+    Blocked { after: usize },
+    Removed,
+}
+
+impl JobEventKind {
+    // the name of the event, the same as the serialized one
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobEventKind::Added { .. } => "added",
+            JobEventKind::Started { .. } => "started",
+            JobEventKind::Progress { .. } => "progress",
+            JobEventKind::Retrying => "retrying",
+            JobEventKind::Paused => "paused",
+            JobEventKind::Resumed => "resumed",
+            JobEventKind::Moved { .. } => "moved",
+            JobEventKind::Done { .. } => "done",
+            JobEventKind::NotModified => "not_modified",
+            JobEventKind::Failed { .. } => "failed",
+            JobEventKind::Blocked { .. } => "blocked",
+            JobEventKind::Removed => "removed",
+        }
+    }
+}
+
+impl JobEvent {
+    pub fn new(obj: &DownloadObject, kind: JobEventKind) -> Self {
+        JobEvent {
+            id: obj.id,
+            name: obj.name.clone(),
+            queue: obj.queue.clone(),
+            at: Utc::now(),
+            kind,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct EventBus {
+    sender: broadcast::Sender<JobEvent>,
+    // the scheduler publishes behind shared references to its lists, so the metrics are behind a lock
+    metrics: Mutex<Metrics>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        EventBus {
+            sender,
+            metrics: Mutex::new(Metrics::default()),
+        }
+    }
+}
+
+impl EventBus {
+    // without subscribers the event is dropped, the metrics record it anyway
+    pub fn publish(&self, obj: &DownloadObject, kind: JobEventKind) {
+        self.metrics.lock_or_recover().record(obj, &kind);
+        let _ = self.sender.send(JobEvent::new(obj, kind));
+    }
+
+    pub fn metrics(&self) -> MutexGuard<'_, Metrics> {
+        self.metrics.lock_or_recover()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<JobEvent> {
+        self.sender.subscribe()
+    }
+}
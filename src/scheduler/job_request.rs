@@ -0,0 +1,26 @@
+use super::download_object::State;
+use crate::commands::AddOptions;
+
+/*
+ * A JobRequest is what the REST resources of the server ask the scheduler about its jobs
+ *
+ * - Every request is answered on its own reply channel with a Reply, the same as the commands of the cli
+ * - The code of the error decides the status code the server answers with
+ */
+
+#[derive(Debug)]
+pub enum JobRequest {
+    Add(AddOptions),
+    // all jobs or only the jobs in this state
+    List { state: Option<State> },
+    Get(usize),
+    // drop a job, an active one is stopped first
+    Delete { id: usize, delete_file: bool },
+    // stop a job, it stays in the done list as failed
+    Cancel { id: usize, delete_file: bool },
+    Pause(usize),
+    Resume(usize),
+    Retry(usize),
+    // move a waiting job to this position of the waiting list, 0 starts first
+    Move { id: usize, position: usize },
+}
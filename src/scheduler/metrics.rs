@@ -0,0 +1,232 @@
+use super::download_object::{DownloadObject, State};
+use super::job_event::JobEventKind;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+
+/*
+ * The Prometheus metrics of the scheduler, served by the server on GET /metrics
+ *
+ * - The counters and the histograms are fed by the transitions of the jobs, the same ones the event bus publishes
+ * - The gauges (the jobs by state, the throughput and the active executors) are taken from the lists at every scrape
+ * - The downloaded bytes count the bytes of every run: a resumed job counts only what it downloads after its start,
+ *   the bytes of the active jobs are counted while they download
+ * - The counters start from zero at every start of the manager
+ */
+
+// the upper bounds of the buckets in seconds
+const QUEUE_WAIT_BUCKETS: [f64; 10] = [0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 3600.0];
+const DURATION_BUCKETS: [f64; 10] = [1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 7200.0];
+
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+    // the count of the observations under every bound, each one includes the ones before it
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum);
+        let _ = writeln!(out, "{}_count {}", name, self.count);
+    }
+}
+
+// an active job at the time of a scrape
+#[derive(Debug)]
+pub struct ActiveJob {
+    pub id: usize,
+    // the bytes downloaded by its run, a resumed run doesn't count its partial file
+    pub downloaded: u64,
+    pub throughput: Option<f64>,
+}
+
+// what the scheduler holds at the time of a scrape
+#[derive(Debug)]
+pub struct Snapshot {
+    pub states: Vec<State>,
+    pub active: Vec<ActiveJob>,
+    pub executors: usize,
+}
+
+#[derive(Debug)]
+pub struct Metrics {
+    // the bytes of the finished runs
+    downloaded_bytes: u64,
+    // the active jobs whose run started since the manager started
+    running: HashSet<usize>,
+    errors: BTreeMap<(String, &'static str), u64>,
+    retries: u64,
+    queue_wait: Histogram,
+    duration: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            downloaded_bytes: 0,
+            running: HashSet::new(),
+            errors: BTreeMap::new(),
+            retries: 0,
+            queue_wait: Histogram::new(&QUEUE_WAIT_BUCKETS),
+            duration: Histogram::new(&DURATION_BUCKETS),
+        }
+    }
+}
+
+impl Metrics {
+    // the transition of a job, the job is already in its new state
+    pub fn record(&mut self, obj: &DownloadObject, kind: &JobEventKind) {
+        match kind {
+            JobEventKind::Started { .. } => {
+                self.running.insert(obj.id);
+                if let Some(attempt) = obj.attempts.last() {
+                    self.queue_wait.observe(seconds(attempt.started_at - attempt.queued_at));
+                }
+            }
+            JobEventKind::Done { .. } | JobEventKind::NotModified | JobEventKind::Failed { .. } => {
+                if self.end_run(obj) {
+                    if let Some(duration) = obj.attempts.last().and_then(|attempt| attempt.duration()) {
+                        self.duration.observe(seconds(duration));
+                    }
+                }
+                if let JobEventKind::Failed { error: Some(error) } = kind {
+                    *self.errors.entry((host(&obj.url), error.kind())).or_default() += 1;
+                }
+            }
+            JobEventKind::Paused => {
+                self.end_run(obj);
+            }
+            JobEventKind::Retrying => self.retries += 1,
+            _ => (),
+        }
+    }
+
+    // the bytes of a run are counted when it ends, the function returns if the job had a run
+    fn end_run(&mut self, obj: &DownloadObject) -> bool {
+        if !self.running.remove(&obj.id) {
+            return false;
+        }
+        if let Some(attempt) = obj.attempts.last() {
+            let size = obj.final_size.unwrap_or(obj.downloaded_size);
+            self.downloaded_bytes += size.saturating_sub(attempt.resumed_from);
+        }
+        true
+    }
+
+    // the metrics in the text format of Prometheus
+    pub fn render(&self, snapshot: &Snapshot) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP manager_jobs The jobs of the scheduler by state");
+        let _ = writeln!(out, "# TYPE manager_jobs gauge");
+        for state in State::ALL {
+            let count = snapshot.states.iter().filter(|s| **s == state).count();
+            let _ = writeln!(out, "manager_jobs{{state=\"{}\"}} {}", state_label(state), count);
+        }
+
+        let in_flight: u64 = snapshot
+            .active
+            .iter()
+            .filter(|job| self.running.contains(&job.id))
+            .map(|job| job.downloaded)
+            .sum();
+        let _ = writeln!(out, "# HELP manager_downloaded_bytes_total The bytes downloaded since the manager started");
+        let _ = writeln!(out, "# TYPE manager_downloaded_bytes_total counter");
+        let _ = writeln!(out, "manager_downloaded_bytes_total {}", self.downloaded_bytes + in_flight);
+
+        // an empty sum of floats is -0.0, the fold starts from 0.0
+        let throughput = snapshot.active.iter().filter_map(|job| job.throughput).fold(0.0, |a, b| a + b);
+        let _ = writeln!(out, "# HELP manager_throughput_bytes_per_second The speed of all the active downloads");
+        let _ = writeln!(out, "# TYPE manager_throughput_bytes_per_second gauge");
+        let _ = writeln!(out, "manager_throughput_bytes_per_second {}", throughput);
+
+        let _ = writeln!(out, "# HELP manager_download_errors_total The failed downloads by host and kind of error");
+        let _ = writeln!(out, "# TYPE manager_download_errors_total counter");
+        for ((host, kind), count) in &self.errors {
+            let _ = writeln!(
+                out,
+                "manager_download_errors_total{{host=\"{}\",kind=\"{}\"}} {}",
+                escape(host),
+                kind,
+                count
+            );
+        }
+
+        let _ = writeln!(out, "# HELP manager_retries_total The failed jobs moved back to the waiting list");
+        let _ = writeln!(out, "# TYPE manager_retries_total counter");
+        let _ = writeln!(out, "manager_retries_total {}", self.retries);
+
+        self.queue_wait.render(
+            &mut out,
+            "manager_queue_wait_seconds",
+            "The time the jobs waited in the waiting list before they started",
+        );
+        self.duration.render(
+            &mut out,
+            "manager_download_duration_seconds",
+            "The time of the runs that finished, failed ones included",
+        );
+
+        let _ = writeln!(out, "# HELP manager_active_executors The running download executors");
+        let _ = writeln!(out, "# TYPE manager_active_executors gauge");
+        let _ = writeln!(out, "manager_active_executors {}", snapshot.executors);
+
+        out
+    }
+}
+
+fn seconds(duration: chrono::Duration) -> f64 {
+    duration.num_milliseconds().max(0) as f64 / 1000.0
+}
+
+// the host of the url of a job, the urls that can't be parsed are counted together
+fn host(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from))
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+fn state_label(state: State) -> &'static str {
+    match state {
+        State::Waiting => "waiting",
+        State::Active => "active",
+        State::Done => "done",
+        State::NotModified => "not_modified",
+        State::Failed => "failed",
+        State::Blocked => "blocked",
+        State::Paused => "paused",
+    }
+}
+
+// the values of the labels are quoted, their quotes and backslashes are escaped
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -1,7 +1,25 @@
 mod download_scheduler;
 mod download_object;
 mod download_executor;
+mod download_schedule;
+mod download_group;
+mod download_queue;
+mod scheduler_event;
+mod client_factory;
+mod supervisor;
+mod download_error;
+mod saved_state;
+mod job_request;
+mod response;
+mod job_event;
+mod metrics;
 
 
 pub use download_scheduler::*;
-pub use download_object::*;
\ No newline at end of file
+pub use scheduler_event::*;
+pub use client_factory::ClientFactory;
+pub use download_object::State;
+pub use download_error::DownloadError;
+pub use job_request::*;
+pub use response::*;
+pub use job_event::JobEvent;
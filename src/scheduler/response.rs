@@ -0,0 +1,300 @@
+use super::download_error::DownloadError;
+use super::download_object::{Attempt, DownloadObject, State, Validators};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/*
+ * A Response is the answer of the server to the commands of the cli and to the REST resources
+ *
+ * - It is json: the api_version, the type of the body and the fields of the body
+ * - The scheduler builds the body from its lists, the cli renders it as text or prints the json as it is
+ * - An error has a code, the server answers it with the status code of the code
+ * - The api version changes when a field is removed or changes its meaning, new fields keep it
+ */
+
+pub const API_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub api_version: u32,
+    #[serde(flatten)]
+    pub body: ResponseBody,
+}
+
+impl Response {
+    pub fn new(body: ResponseBody) -> Self {
+        Response {
+            api_version: API_VERSION,
+            body,
+        }
+    }
+}
+
+impl From<Reply> for Response {
+    fn from(reply: Reply) -> Self {
+        Response::new(reply.unwrap_or_else(ResponseBody::Error))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseBody {
+    // a command that changed a setting or a job, like Retention or Retry
+    Message { message: String },
+    // Add, Redownload and Clone answer the id of the new job
+    Added { id: usize },
+    // RetryAllFailed and Purge answer how many jobs they changed
+    Count { count: usize, message: String },
+    Job { job: Box<JobView> },
+    Jobs { jobs: Vec<JobView>, counts: JobCounts },
+    Queues { queues: Vec<QueueView> },
+    Groups { groups: Vec<GroupView> },
+    Schedules { schedules: Vec<ScheduleView> },
+    Error(ApiError),
+}
+
+impl ResponseBody {
+    pub fn message(message: impl fmt::Display) -> Self {
+        ResponseBody::Message { message: message.to_string() }
+    }
+
+    pub fn job(obj: &DownloadObject) -> Self {
+        ResponseBody::Job { job: Box::new(JobView::from(obj)) }
+    }
+
+    pub fn jobs(jobs: Vec<JobView>) -> Self {
+        ResponseBody::Jobs {
+            counts: JobCounts::of(&jobs),
+            jobs,
+        }
+    }
+}
+
+// the scheduler answers every command and request with a body or an error
+pub type Reply = Result<ResponseBody, ApiError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    NotFound,
+    // the request has no token or an unknown one
+    Unauthorized,
+    // the token is known but its scope doesn't allow the request
+    Forbidden,
+    // the request itself is wrong, like an unknown queue or an invalid cron expression
+    InvalidRequest,
+    // the body of the request is larger than the limit of the server
+    PayloadTooLarge,
+    // the client didn't send its request in time
+    RequestTimeout,
+    // the request is right but the job or the queue is not in a state that allows it
+    Conflict,
+    // the manager is shutting down and doesn't accept new jobs
+    ShuttingDown,
+    // the scheduler is not running
+    Unavailable,
+    // the scheduler didn't answer in time
+    Timeout,
+    Internal,
+}
+
+impl ErrorCode {
+    // the name of the code, the same as the serialized one
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::PayloadTooLarge => "payload_too_large",
+            ErrorCode::RequestTimeout => "request_timeout",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::ShuttingDown => "shutting_down",
+            ErrorCode::Unavailable => "unavailable",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::Internal => "internal",
+        }
+    }
+
+    // the status code the server answers the error with
+    pub fn status(&self) -> u16 {
+        match self {
+            ErrorCode::NotFound => 404,
+            ErrorCode::Unauthorized => 401,
+            ErrorCode::Forbidden => 403,
+            ErrorCode::InvalidRequest => 400,
+            ErrorCode::PayloadTooLarge => 413,
+            ErrorCode::RequestTimeout => 408,
+            ErrorCode::Conflict => 409,
+            ErrorCode::ShuttingDown | ErrorCode::Unavailable => 503,
+            ErrorCode::Timeout => 504,
+            ErrorCode::Internal => 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl fmt::Display) -> Self {
+        ApiError {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn not_found(message: impl fmt::Display) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn invalid(message: impl fmt::Display) -> Self {
+        Self::new(ErrorCode::InvalidRequest, message)
+    }
+
+    pub fn conflict(message: impl fmt::Display) -> Self {
+        Self::new(ErrorCode::Conflict, message)
+    }
+
+    pub fn internal(message: impl fmt::Display) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+
+    pub fn shutting_down() -> Self {
+        Self::new(ErrorCode::ShuttingDown, "The manager is shutting down, it doesn't accept new jobs")
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// a snapshot of a download object, the object itself never leaves the scheduler
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobView {
+    pub id: usize,
+    pub name: String,
+    pub url: String,
+    pub download_path: String,
+    pub state: State,
+    pub queue: String,
+    pub group: Option<String>,
+    pub after: Vec<usize>,
+    pub total_size: Option<u64>,
+    pub downloaded_size: u64,
+    pub final_size: Option<u64>,
+    pub error: Option<DownloadError>,
+    pub validators: Validators,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    // the milliseconds the job waited in the waiting list before its last start
+    pub queue_wait_ms: i64,
+    // the average speed of the last attempt in bytes per second
+    pub throughput: Option<f64>,
+    // the speed of an active job since it started, like the one of its progress events
+    #[serde(default)]
+    pub speed: Option<f64>,
+    pub attempts: Vec<Attempt>,
+}
+
+impl From<&DownloadObject> for JobView {
+    fn from(obj: &DownloadObject) -> Self {
+        JobView {
+            id: obj.id,
+            name: obj.name.clone(),
+            url: obj.url.clone(),
+            download_path: obj.download_path.clone(),
+            state: obj.state,
+            queue: obj.queue.clone(),
+            group: obj.group.clone(),
+            after: obj.after.clone(),
+            total_size: obj.total_size,
+            downloaded_size: obj.downloaded_size,
+            final_size: obj.final_size,
+            error: obj.resulted_err.clone(),
+            validators: obj.validators.clone(),
+            created_at: obj.created_at,
+            started_at: obj.started_at,
+            finished_at: obj.finished_at,
+            queue_wait_ms: obj.queue_wait().num_milliseconds(),
+            throughput: obj.throughput(),
+            speed: Some(obj)
+                .filter(|obj| obj.state == State::Active)
+                .and_then(|obj| obj.live_throughput(Utc::now())),
+            attempts: obj.attempts.clone(),
+        }
+    }
+}
+
+// how many of the listed jobs are in every state
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct JobCounts {
+    pub total: usize,
+    pub waiting: usize,
+    pub active: usize,
+    pub paused: usize,
+    pub done: usize,
+    pub not_modified: usize,
+    pub failed: usize,
+    pub blocked: usize,
+}
+
+impl JobCounts {
+    pub fn of(jobs: &[JobView]) -> Self {
+        let mut counts = JobCounts {
+            total: jobs.len(),
+            ..Default::default()
+        };
+        for job in jobs {
+            match job.state {
+                State::Waiting => counts.waiting += 1,
+                State::Active => counts.active += 1,
+                State::Paused => counts.paused += 1,
+                State::Done => counts.done += 1,
+                State::NotModified => counts.not_modified += 1,
+                State::Failed => counts.failed += 1,
+                State::Blocked => counts.blocked += 1,
+            }
+        }
+
+        counts
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueView {
+    pub name: String,
+    pub max_jobs: usize,
+    // the download path of the queue, or the one of the manager if the queue has none
+    pub download_path: String,
+    pub speed_limit: Option<u64>,
+    pub jobs: Vec<JobView>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupView {
+    pub name: String,
+    pub hook: Option<String>,
+    pub fired: bool,
+    pub hook_error: Option<String>,
+    pub jobs: usize,
+    // the jobs that finished successfully, Done or NotModified
+    pub done: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleView {
+    pub id: usize,
+    pub url: String,
+    pub cron: String,
+    pub download_path: String,
+    pub name_template: String,
+    pub next_fire: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,96 @@
+use super::download_object::{DownloadObject, Validators};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/*
+ * The SavedState is what the scheduler writes to the disk when it shuts down and reads back when it starts
+ *
+ * - The jobs of all lists are saved, the paused ones are in the waiting list and resume their partial files
+ * - The queues, the groups and the schedules are saved with their settings, the schedules compute their next fire time again
+ * - The default queue is not restored, the Start command sets it
+ * - The state is written to a temporary file which is renamed, a crash while saving doesn't lose the previous state
+ * - The state file is removed once the restored jobs are served, a later start doesn't restore the same jobs twice
+ * - A state file that can't be restored stops the start, it is left on the disk as it is
+ */
+
+pub const STATE_FILE: &str = ".manager-state.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedState {
+    pub next_id: usize,
+    pub next_schedule_id: usize,
+    pub queues: Vec<SavedQueue>,
+    pub groups: Vec<SavedGroup>,
+    pub schedules: Vec<SavedSchedule>,
+    pub waiting: Vec<DownloadObject>,
+    pub done: Vec<DownloadObject>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedQueue {
+    pub name: String,
+    pub max_jobs: usize,
+    pub download_path: Option<String>,
+    pub speed_limit: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedGroup {
+    pub name: String,
+    pub hook: Option<String>,
+    pub fired: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedSchedule {
+    // the state files of the older versions have no schedule ids, their schedules get new ones
+    pub id: Option<usize>,
+    pub url: String,
+    pub cron: String,
+    pub download_path: String,
+    pub name_template: String,
+    pub validators: Option<Validators>,
+}
+
+impl SavedState {
+    // the state file is kept in the default download path
+    pub fn path(download_path: &str) -> PathBuf {
+        PathBuf::from(download_path).join(STATE_FILE)
+    }
+
+    // returns None if the manager never saved its state in this download path
+    pub fn load(download_path: &str) -> anyhow::Result<Option<Self>> {
+        let path = Self::path(download_path);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(anyhow::anyhow!("Failed to read {}: {}", path.display(), e)),
+        };
+
+        serde_json::from_str(content.as_str())
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("Invalid state file {}: {}", path.display(), e))
+    }
+
+    // the restored state is consumed, the jobs are saved again at the next shutdown
+    pub fn remove(download_path: &str) -> anyhow::Result<()> {
+        let path = Self::path(download_path);
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(anyhow::anyhow!("Failed to remove {}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn save(&self, download_path: &str) -> anyhow::Result<()> {
+        let path = Self::path(download_path);
+        let temporary = path.with_extension("json.tmp");
+
+        std::fs::create_dir_all(download_path)?;
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(&temporary, content)
+            .and_then(|_| std::fs::rename(&temporary, &path))
+            .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path.display(), e))
+    }
+}
@@ -0,0 +1,27 @@
+use super::job_event::JobEvent;
+use super::job_request::JobRequest;
+use super::response::Reply;
+use crate::commands::Manager;
+use tokio::sync::{broadcast, oneshot};
+
+// everything the scheduler thread reacts to comes through one channel as an event
+#[derive(Debug)]
+pub enum SchedulerEvent {
+    // a command received by the server, the reply is sent back on the reply channel of the request
+    Command(Box<Manager>, oneshot::Sender<Reply>),
+    // a request of the REST resources of the server, answered the same way
+    Job(JobRequest, oneshot::Sender<Reply>),
+    // a stream of the server asks for a receiver of the event bus
+    Subscribe(oneshot::Sender<broadcast::Receiver<JobEvent>>),
+    // the server asks for the metrics in the text format of Prometheus
+    Metrics(oneshot::Sender<String>),
+    // an executor wrote more bytes of its file to the disk
+    Progress { id: usize, downloaded: u64 },
+    // the thread of an executor finished, either the job is Done or it Failed
+    Finished(usize),
+    // sent every second for the schedules and the retention policy
+    Tick,
+}
+
+// the events channel is bounded, when the scheduler falls behind the senders wait for it
+pub const EVENTS_CAPACITY: usize = 1024;
@@ -0,0 +1,46 @@
+use futures::FutureExt;
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/*
+ * The supervisor keeps a panic of one job from taking the scheduler down
+ *
+ * - The executors run their download through catch_panic, a panic fails the job with the panic message
+ * - The scheduler handles every command through catch_panic, a panic answers the command with the panic message
+ * - Every step of the tick runs through catch_panic too, a panic skips the step until the next tick
+ * - A panic while a lock is held poisons it, lock_or_recover takes the lock anyway
+ *   the data is still usable because the writers set whole fields
+ */
+
+pub trait LockOrRecover<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> LockOrRecover<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// run the future and turn its panic into an error message
+pub async fn catch_panic<F: Future>(future: F) -> Result<F::Output, String> {
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .map_err(panic_message)
+}
+
+// a panic carries a &str or a String in most cases
+pub fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    let message = if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic")
+    };
+
+    format!("Panicked: {}", message)
+}
@@ -0,0 +1,211 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::{header, Method};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::scheduler::{ApiError, ErrorCode};
+
+use super::rpc::RPC_PATH;
+
+/*
+ * The tokens of the control server
+ *
+ * - Every request has to send one of them as a bearer token: Authorization: Bearer <token>
+ * - A read token can only look at the jobs: the GET resources and the List and Info commands
+ * - A full token can do everything
+ * - The first start generates a full and a read token in the token file, only its owner can read it
+ * - More tokens are added by editing the file, the server reads it when it starts
+ * - The cli reads the same file and sends its full token, or its read token if it has no full one
+ * - The requests of the unix socket need no token, the permissions of the socket decide who can send them
+ * - The files of the dashboard need no token either, they hold no data
+ * - The json-rpc calls of aria2 send their token in their params, /jsonrpc checks it itself
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Read,
+    Full,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Token {
+    // only to tell the tokens apart in the file
+    pub name: String,
+    pub token: String,
+    pub scope: Scope,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenFile {
+    pub tokens: Vec<Token>,
+}
+
+impl TokenFile {
+    // $XDG_CONFIG_HOME/manager/tokens.json, ~/.config/manager/tokens.json or %APPDATA%\manager\tokens.json
+    // if none of them is set the file is in the working directory
+    pub fn default_path() -> PathBuf {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from));
+
+        match config_dir {
+            Some(dir) => dir.join("manager").join("tokens.json"),
+            None => PathBuf::from(".manager-tokens.json"),
+        }
+    }
+
+    // the file is missing if the server never started with this path
+    pub fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(anyhow::anyhow!("Failed to read the tokens in {}: {}", path.display(), e)),
+        };
+
+        let tokens = serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Invalid token file {}: {}", path.display(), e))?;
+        Ok(Some(tokens))
+    }
+
+    // the server loads its tokens, the first start generates them
+    pub fn load_or_generate(path: &Path) -> anyhow::Result<Self> {
+        if let Some(tokens) = Self::load(path)? {
+            if tokens.tokens.is_empty() {
+                return Err(anyhow::anyhow!("The token file {} has no tokens", path.display()));
+            }
+            Self::warn_if_readable(path);
+            return Ok(tokens);
+        }
+
+        let tokens = TokenFile {
+            tokens: vec![
+                Token {
+                    name: String::from("full"),
+                    token: Self::generate_token(),
+                    scope: Scope::Full,
+                },
+                Token {
+                    name: String::from("read"),
+                    token: Self::generate_token(),
+                    scope: Scope::Read,
+                },
+            ],
+        };
+        tokens.save(path)?;
+        println!("Generated the API tokens in {}", path.display());
+
+        Ok(tokens)
+    }
+
+    // the token of the cli, the full one if there is one
+    pub fn client_token(&self) -> Option<&str> {
+        self.tokens
+            .iter()
+            .find(|token| token.scope == Scope::Full)
+            .or_else(|| self.tokens.first())
+            .map(|token| token.token.as_str())
+    }
+
+    // the scope of the bearer token of a request, the request is refused without a known token
+    // a read token is refused for everything but the GET resources, /command and /jsonrpc check the calls themselves
+    // the requests that need no token have no scope
+    pub fn authorize(&self, req: &ServiceRequest) -> Result<Option<Scope>, ApiError> {
+        #[cfg(unix)]
+        if req.conn_data::<super::socket::LocalConnection>().is_some() {
+            return Ok(Some(Scope::Full));
+        }
+
+        if req.method() == Method::GET && super::dashboard::is_public(req.path()) {
+            return Ok(None);
+        }
+
+        let bearer = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+
+        // the json-rpc calls can send their token in their params, like the ones of aria2
+        if bearer.is_none() && req.path() == RPC_PATH {
+            return Ok(None);
+        }
+
+        let scope = bearer
+            .and_then(|bearer| self.scope_of(bearer))
+            .ok_or_else(|| ApiError::new(ErrorCode::Unauthorized, "A valid bearer token is required"))?;
+
+        if scope == Scope::Read && req.method() != Method::GET && !matches!(req.path(), "/command" | RPC_PATH) {
+            return Err(Self::forbidden());
+        }
+
+        Ok(Some(scope))
+    }
+
+    // the scope of a known token
+    pub fn scope_of(&self, token: &str) -> Option<Scope> {
+        self.tokens
+            .iter()
+            .find(|known| Self::same_token(known.token.as_bytes(), token.as_bytes()))
+            .map(|known| known.scope)
+    }
+
+    pub fn forbidden() -> ApiError {
+        ApiError::new(ErrorCode::Forbidden, "The token is read only")
+    }
+
+    // 32 random bytes as hex
+    fn generate_token() -> String {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    // the time of the comparison doesn't depend on where the tokens differ
+    fn same_token(a: &[u8], b: &[u8]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+
+    // the directory and the file are created for the owner only
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        let fail = |e: std::io::Error| anyhow::anyhow!("Failed to write the tokens in {}: {}", path.display(), e);
+
+        let mut dir_builder = fs::DirBuilder::new();
+        let mut options = fs::OpenOptions::new();
+        dir_builder.recursive(true);
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+            dir_builder.mode(0o700);
+            options.mode(0o600);
+        }
+
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            dir_builder.create(dir).map_err(fail)?;
+        }
+        let mut file = options.open(path).map_err(fail)?;
+        file.write_all(content.as_bytes()).map_err(fail)?;
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn warn_if_readable(path: &Path) {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(metadata) = fs::metadata(path) {
+            if metadata.permissions().mode() & 0o077 != 0 {
+                eprintln!("Warning: the token file {} can be read by other users", path.display());
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn warn_if_readable(_path: &Path) {}
+}
@@ -0,0 +1,50 @@
+use actix_web::http::header;
+use actix_web::{get, web, HttpResponse};
+
+/*
+ * The web dashboard of the manager
+ *
+ * - The page, its script and its style are built into the binary and served on the same addresses as the api
+ * - They hold no data, the browser loads them without a token and the page sends its token with every request
+ * - The page uses only the REST resources: GET /jobs, GET /events and the resources of a job
+ * - The token is kept in the local storage of the browser, it can be given once as /#token=<token>
+ */
+
+const INDEX: &str = include_str!("dashboard/index.html");
+const SCRIPT: &str = include_str!("dashboard/app.js");
+const STYLE: &str = include_str!("dashboard/app.css");
+
+// the page only loads its own files and talks to its own server
+const CONTENT_SECURITY_POLICY: &str = "default-src 'self'; frame-ancestors 'none'";
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(index).service(script).service(style);
+}
+
+// the paths the authorization lets through without a token
+pub fn is_public(path: &str) -> bool {
+    matches!(path, "/" | "/ui/app.js" | "/ui/app.css")
+}
+
+#[get("/")]
+async fn index() -> HttpResponse {
+    serve("text/html; charset=utf-8", INDEX)
+}
+
+#[get("/ui/app.js")]
+async fn script() -> HttpResponse {
+    serve("text/javascript; charset=utf-8", SCRIPT)
+}
+
+#[get("/ui/app.css")]
+async fn style() -> HttpResponse {
+    serve("text/css; charset=utf-8", STYLE)
+}
+
+fn serve(content_type: &str, content: &'static str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((header::CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY))
+        .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .body(content)
+}
@@ -0,0 +1,473 @@
+"use strict";
+
+// the dashboard talks to the REST resources of the server it is loaded from
+// the jobs are loaded with GET /jobs and kept up to date with the events of GET /events
+
+const TOKEN_KEY = "manager-token";
+const RECONNECT_MS = 3000;
+
+const state = {
+    token: localStorage.getItem(TOKEN_KEY) || "",
+    jobs: [],
+    selected: null,
+    stream: null,
+    reload: null,
+};
+
+const $ = (id) => document.getElementById(id);
+
+// ---------- the api ----------
+
+function headers(json) {
+    const headers = {};
+    if (state.token) {
+        headers["Authorization"] = "Bearer " + state.token;
+    }
+    if (json) {
+        headers["Content-Type"] = "application/json";
+    }
+    return headers;
+}
+
+// every answer is a versioned response, the errors have a code and a message
+async function api(method, path, body) {
+    const resp = await fetch(path, {
+        method,
+        headers: headers(body !== undefined),
+        body: body === undefined ? undefined : JSON.stringify(body),
+    });
+
+    let response;
+    try {
+        response = await resp.json();
+    } catch (e) {
+        throw new Error("The server answered " + resp.status);
+    }
+    if (response.type === "error") {
+        throw new Error(response.message + " (" + response.code + ")");
+    }
+    return response;
+}
+
+// run an action of a button, its error is shown on top of the page
+async function act(action) {
+    try {
+        await action();
+        showError(null);
+    } catch (e) {
+        showError(e.message);
+    }
+}
+
+function showError(message) {
+    $("error").hidden = !message;
+    $("error").textContent = message || "";
+}
+
+function setStatus(text, live) {
+    $("status").textContent = text;
+    $("status").classList.toggle("live", !!live);
+}
+
+// ---------- the jobs ----------
+
+async function loadJobs() {
+    const response = await api("GET", "/jobs");
+    state.jobs = response.jobs;
+    renderJobs(response.counts);
+    if (state.selected !== null) {
+        await loadDetails(state.selected);
+    }
+}
+
+// the events that change more than the progress reload the list, the events that arrive together reload it once
+function scheduleReload() {
+    if (state.reload) {
+        return;
+    }
+    state.reload = setTimeout(() => {
+        state.reload = null;
+        act(loadJobs);
+    }, 200);
+}
+
+function renderJobs(counts) {
+    // the waiting list comes first in the order the jobs start, paused jobs keep their place in it
+    const waiting = state.jobs.filter((job) => job.state === "Waiting" || job.state === "Paused");
+    const active = state.jobs.filter((job) => job.state === "Active");
+    const finished = state.jobs.filter((job) => !waiting.includes(job) && !active.includes(job));
+
+    $("active").replaceChildren(...active.map((job) => jobRow(job, null)));
+    $("waiting").replaceChildren(...waiting.map((job, index) => jobRow(job, { index, count: waiting.length })));
+    $("finished").replaceChildren(...finished.map((job) => jobRow(job, null)));
+
+    if (counts) {
+        $("counts").textContent = counts.total + " jobs, " + counts.active + " active, " + counts.waiting
+            + " waiting, " + counts.done + " done, " + counts.failed + " failed";
+    }
+}
+
+function jobRow(job, place) {
+    const row = document.createElement("tr");
+    row.id = "job-" + job.id;
+    row.className = "state-" + job.state.toLowerCase();
+
+    const name = button(job.name, () => act(() => loadDetails(job.id)));
+    name.className = "link";
+
+    row.append(
+        cell(String(job.id)),
+        cell(name),
+        cell(job.queue),
+        cell(job.state + (job.error ? " (" + job.error.kind + ")" : "")),
+        progressCell(job),
+        cell(speed(job)),
+        cell(...actions(job, place)),
+    );
+    return row;
+}
+
+function progressCell(job) {
+    const td = document.createElement("td");
+    td.className = "progress";
+    const bar = document.createElement("progress");
+    const downloaded = job.final_size ?? job.downloaded_size;
+    if (job.total_size) {
+        bar.max = job.total_size;
+        bar.value = Math.min(downloaded, job.total_size);
+    } else if (job.state === "Done" || job.state === "NotModified") {
+        bar.max = 1;
+        bar.value = 1;
+    } else {
+        bar.removeAttribute("value");
+    }
+    const text = document.createElement("span");
+    text.textContent = size(downloaded) + (job.total_size ? " / " + size(job.total_size) : "");
+    td.append(bar, text);
+    return td;
+}
+
+function actions(job, place) {
+    const id = job.id;
+    const buttons = [];
+
+    if (job.state === "Active" || job.state === "Waiting") {
+        buttons.push(button("Pause", () => act(() => api("POST", "/jobs/" + id + "/pause"))));
+    }
+    if (job.state === "Paused") {
+        buttons.push(button("Resume", () => act(() => api("POST", "/jobs/" + id + "/resume"))));
+    }
+    if (job.state === "Failed" || job.state === "Blocked") {
+        buttons.push(button("Retry", () => act(() => api("POST", "/jobs/" + id + "/retry"))));
+    }
+    if (place) {
+        const up = button("↑", () => act(() => move(id, place.index - 1)));
+        const down = button("↓", () => act(() => move(id, place.index + 1)));
+        up.title = "Start earlier";
+        down.title = "Start later";
+        up.disabled = place.index === 0;
+        down.disabled = place.index === place.count - 1;
+        buttons.push(up, down);
+    }
+
+    const finished = !place && job.state !== "Active";
+    buttons.push(button(finished ? "Remove" : "Cancel", () => act(() => cancel(job))));
+    return buttons;
+}
+
+function move(id, position) {
+    return api("POST", "/jobs/" + id + "/move", { position });
+}
+
+// a cancelled job stays in the list as failed and can be retried, a finished job is removed from the list
+async function cancel(job) {
+    const unfinished = job.state === "Active" || job.state === "Waiting" || job.state === "Paused";
+    const question = unfinished
+        ? "Cancel the job " + job.id + " (" + job.name + ")? A partial file stays on the disk."
+        : "Remove the job " + job.id + " (" + job.name + ") from the list? The file stays on the disk.";
+    if (!confirm(question)) {
+        return;
+    }
+
+    if (unfinished) {
+        await api("POST", "/jobs/" + job.id + "/cancel");
+        return;
+    }
+    await api("DELETE", "/jobs/" + job.id);
+    if (state.selected === job.id) {
+        closeDetails();
+    }
+}
+
+// the progress events only change the numbers of a job
+function updateProgress(event) {
+    const job = state.jobs.find((job) => job.id === event.id);
+    if (!job) {
+        scheduleReload();
+        return;
+    }
+    job.downloaded_size = event.downloaded;
+    job.total_size = event.total_size;
+    job.throughput = event.throughput;
+
+    const row = $("job-" + job.id);
+    if (row) {
+        row.children[4].replaceWith(progressCell(job));
+        row.children[5].textContent = speed(job);
+    }
+}
+
+// ---------- the details of a job ----------
+
+async function loadDetails(id) {
+    const response = await api("GET", "/jobs/" + id);
+    const job = response.job;
+    state.selected = id;
+
+    const fields = [
+        ["Name", job.name],
+        ["Url", job.url],
+        ["Download path", job.download_path],
+        ["State", job.state],
+        ["Queue", job.queue],
+        ["Group", job.group],
+        ["After", job.after.length ? job.after.join(", ") : null],
+        ["Size", job.total_size !== null ? size(job.total_size) : null],
+        ["Downloaded", size(job.final_size ?? job.downloaded_size)],
+        ["Error", job.error ? errorText(job.error) : null],
+        ["ETag", job.validators.etag],
+        ["Last modified", job.validators.last_modified],
+        ["Created", time(job.created_at)],
+        ["Started", time(job.started_at)],
+        ["Finished", time(job.finished_at)],
+        ["Waited", duration(job.queue_wait_ms)],
+        ["Speed", job.throughput !== null ? size(job.throughput) + "/s" : null],
+    ];
+
+    const list = $("details-fields");
+    list.replaceChildren();
+    for (const [name, value] of fields) {
+        if (value === null || value === undefined) {
+            continue;
+        }
+        const dt = document.createElement("dt");
+        const dd = document.createElement("dd");
+        dt.textContent = name;
+        dd.textContent = value;
+        if (name === "Error") {
+            dd.className = "failed";
+        }
+        list.append(dt, dd);
+    }
+
+    $("details-attempts").replaceChildren(...job.attempts.map((attempt) => {
+        const row = document.createElement("tr");
+        row.append(
+            cell(time(attempt.queued_at)),
+            cell(time(attempt.started_at)),
+            cell(time(attempt.finished_at) || ""),
+            cell(size(attempt.downloaded)),
+            cell(attempt.state),
+        );
+        return row;
+    }));
+
+    $("details-id").textContent = job.id;
+    $("details").hidden = false;
+}
+
+function closeDetails() {
+    state.selected = null;
+    $("details").hidden = true;
+}
+
+// the error of a job keeps its cause, the http status keeps its code too
+function errorText(error) {
+    switch (error.kind) {
+        case "http_status":
+            return "http status " + error.status + ": " + error.cause;
+        case "checksum":
+            return "checksum: expected " + error.expected + ", got " + error.actual;
+        case "blocked":
+            return "blocked: the job " + error.after + " didn't finish successfully";
+        default:
+            return error.kind + ": " + error.cause;
+    }
+}
+
+// ---------- the events ----------
+
+// EventSource can't send the token, the stream is read with fetch
+async function connect() {
+    if (state.stream) {
+        state.stream.abort();
+    }
+    const controller = new AbortController();
+    state.stream = controller;
+
+    try {
+        const resp = await fetch("/events", { headers: headers(false), signal: controller.signal });
+        if (!resp.ok) {
+            let message = "The server answered " + resp.status;
+            try {
+                message = (await resp.json()).message;
+            } catch (e) {
+                // the status is enough
+            }
+            throw new Error(message);
+        }
+
+        // the stream is subscribed, the list loaded now misses no event
+        setStatus("live", true);
+        await loadJobs();
+        showError(null);
+
+        const reader = resp.body.pipeThrough(new TextDecoderStream()).getReader();
+        let buffer = "";
+        for (;;) {
+            const { value, done } = await reader.read();
+            if (done) {
+                break;
+            }
+            buffer += value;
+            let end;
+            while ((end = buffer.indexOf("\n\n")) >= 0) {
+                handleEvent(buffer.slice(0, end));
+                buffer = buffer.slice(end + 2);
+            }
+        }
+    } catch (e) {
+        if (controller.signal.aborted) {
+            return;
+        }
+        showError(e.message);
+    }
+
+    if (state.stream === controller) {
+        setStatus("disconnected, reconnecting", false);
+        setTimeout(connect, RECONNECT_MS);
+    }
+}
+
+// an event is an "event:" line and a "data:" line, the comments keep the connection alive
+function handleEvent(block) {
+    let name = null;
+    let data = "";
+    for (const line of block.split("\n")) {
+        if (line.startsWith("event:")) {
+            name = line.slice(6).trim();
+        } else if (line.startsWith("data:")) {
+            data += line.slice(5).trim();
+        }
+    }
+    if (!name) {
+        return;
+    }
+
+    if (name === "progress") {
+        updateProgress(JSON.parse(data));
+    } else {
+        scheduleReload();
+    }
+}
+
+// ---------- the forms ----------
+
+function addOptions(form) {
+    const value = (name) => form.elements[name].value.trim() || null;
+    const after = (value("after") || "")
+        .split(/[\s,]+/)
+        .filter((id) => id)
+        .map(Number);
+    if (after.some((id) => !Number.isInteger(id) || id < 0)) {
+        throw new Error("After takes the ids of jobs, like 1, 2");
+    }
+
+    return {
+        url: value("url"),
+        custom_name: value("custom_name"),
+        custom_download_path: value("custom_download_path"),
+        queue: value("queue"),
+        group: value("group"),
+        after,
+        proxy: value("proxy"),
+        headers: (value("headers") || "").split("\n").map((header) => header.trim()).filter((header) => header),
+        only_if_changed: form.elements["only_if_changed"].checked,
+        insecure: form.elements["insecure"].checked,
+    };
+}
+
+function setup() {
+    // a token given in the address is saved and dropped from the address
+    if (location.hash.startsWith("#token=")) {
+        state.token = decodeURIComponent(location.hash.slice(7));
+        localStorage.setItem(TOKEN_KEY, state.token);
+        history.replaceState(null, "", location.pathname);
+    }
+    $("token").value = state.token;
+
+    $("token-form").addEventListener("submit", (e) => {
+        e.preventDefault();
+        state.token = $("token").value.trim();
+        localStorage.setItem(TOKEN_KEY, state.token);
+        connect();
+    });
+
+    $("add-form").addEventListener("submit", (e) => {
+        e.preventDefault();
+        const form = e.target;
+        act(async () => {
+            await api("POST", "/jobs", addOptions(form));
+            form.reset();
+        });
+    });
+
+    $("details-close").addEventListener("click", closeDetails);
+
+    connect();
+}
+
+// ---------- formatting ----------
+
+function cell(...children) {
+    const td = document.createElement("td");
+    td.append(...children);
+    return td;
+}
+
+function button(text, onclick) {
+    const b = document.createElement("button");
+    b.type = "button";
+    b.textContent = text;
+    b.addEventListener("click", onclick);
+    return b;
+}
+
+function size(bytes) {
+    const units = ["B", "KB", "MB", "GB", "TB"];
+    let value = bytes;
+    let unit = 0;
+    while (value >= 1024 && unit < units.length - 1) {
+        value /= 1024;
+        unit += 1;
+    }
+    return (unit === 0 ? value : value.toFixed(1)) + " " + units[unit];
+}
+
+function speed(job) {
+    return job.state === "Active" && job.throughput ? size(job.throughput) + "/s" : "";
+}
+
+function time(at) {
+    return at ? new Date(at).toLocaleString() : null;
+}
+
+function duration(ms) {
+    if (!ms) {
+        return null;
+    }
+    const seconds = Math.round(ms / 1000);
+    return seconds < 60 ? seconds + "s" : Math.floor(seconds / 60) + "m " + (seconds % 60) + "s";
+}
+
+setup();
@@ -0,0 +1,71 @@
+use actix_web::web::Bytes;
+use futures::Stream;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::Interval;
+
+use crate::scheduler::JobEvent;
+
+/*
+ * The server-sent events of GET /events
+ *
+ * - Every event of the bus is sent as "event: <name>" with the job event as json in "data"
+ * - ?job=<id> and ?queue=<name> keep only the events of a job or of a queue
+ * - A comment is sent when nothing happened for a while, so the proxies keep the connection
+ * - A subscriber that fell behind gets a lagged event with the number of the events it lost
+ * - The stream ends when the scheduler stops
+ */
+
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    pub job: Option<usize>,
+    pub queue: Option<String>,
+}
+
+impl EventsQuery {
+    fn matches(&self, event: &JobEvent) -> bool {
+        self.job.is_none_or(|job| job == event.id)
+            && self.queue.as_deref().is_none_or(|queue| queue == event.queue)
+    }
+}
+
+struct Subscription {
+    events: broadcast::Receiver<JobEvent>,
+    keep_alive: Interval,
+    query: EventsQuery,
+}
+
+pub fn stream(
+    events: broadcast::Receiver<JobEvent>,
+    query: EventsQuery,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let subscription = Subscription {
+        events,
+        keep_alive: tokio::time::interval(KEEP_ALIVE),
+        query,
+    };
+
+    futures::stream::unfold(subscription, |mut subscription| async move {
+        loop {
+            let chunk = tokio::select! {
+                event = subscription.events.recv() => match event {
+                    Ok(event) if subscription.query.matches(&event) => to_sse(&event),
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => format!("event: lagged\ndata: {{\"skipped\":{}}}\n\n", skipped),
+                    Err(RecvError::Closed) => return None,
+                },
+                _ = subscription.keep_alive.tick() => String::from(": keep-alive\n\n"),
+            };
+
+            return Some((Ok(Bytes::from(chunk)), subscription));
+        }
+    })
+}
+
+fn to_sse(event: &JobEvent) -> String {
+    let data = serde_json::to_string(event).unwrap_or_default();
+    format!("event: {}\ndata: {}\n\n", event.kind.name(), data)
+}
@@ -1,108 +1,244 @@
-use std::net::TcpListener;
-use std::sync::mpsc;
-
-use crate::commands::Manager;
-use crate::scheduler::Scheduler;
-
+use actix_web::dev::{Extensions, Service};
+use std::any::Any;
+use actix_web::{web, App, HttpMessage, HttpServer};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::commands::{ClientOptions, Manager, ManagerCommands, RetentionPolicy, ServerOptions, TlsOptions};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use crate::scheduler::{ClientFactory, ResponseBody, Scheduler, SchedulerEvent, EVENTS_CAPACITY};
+
+use super::auth::TokenFile;
+use super::request::{self, ReadTimeout};
+use super::{dashboard, routes, rpc};
+#[cfg(unix)]
+use super::socket;
 use super::utils::AppData;
 
-use std::io::prelude::*;
-use std::net::TcpStream;
-
-// this server receives http requests using a tcp listner
-// it runs on only one thread
-// it handles only POST requests on the path /command
-// the benefit of this server is to hold the scheduler run and talk to it a syncronousley
-pub fn main_thread(max_active_downloads: u16, download_path: String) {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let scheduler = Scheduler::new(max_active_downloads as usize, download_path.clone());
-
-    // the communication channels to the shceduler main thread
-    let (thread_tx, server_rx) = mpsc::channel();
-    let (server_tx, thread_rx) = mpsc::channel();
-
-    // running the scheduler forever
-    Scheduler::run(scheduler, thread_rx, thread_tx);
-    let app_data = AppData {
-        server_tx,
-        server_rx,
-        download_path,
-    };
-
-    // serve the requests
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
-
-        handle_connection::<Manager, Result<Vec<String>, String>>(stream, &app_data);
+// the server listens here if the Start command has no --listen, the cli talks to it if it has no --server and no socket
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+
+// this server serves the REST resources of the routes module with actix-web
+// the benefit of this server is to hold the scheduler run and talk to it through its events channel
+// it listens on the tcp addresses, with https if it has a certificate, and on the unix socket if it is given one
+// it stops when the scheduler stops after a Shutdown command or a signal
+#[allow(clippy::too_many_arguments)]
+pub async fn main_thread(
+    max_active_downloads: u16,
+    download_path: String,
+    listen: Vec<String>,
+    socket_path: Option<PathBuf>,
+    token_file: PathBuf,
+    retention: RetentionPolicy,
+    client: ClientOptions,
+    tls: TlsOptions,
+    options: ServerOptions,
+) -> anyhow::Result<()> {
+    if listen.is_empty() && socket_path.is_none() {
+        return Err(anyhow::anyhow!("There is no address or socket to listen on"));
     }
-}
+    let https = tls.enabled()?;
 
-fn handle_connection<S, R>(mut stream: TcpStream, app_data: &AppData<Manager, Vec<String>>) {
-    let mut buffer = [0; 2048];
+    // the server doesn't start without its tokens
+    let tokens = Arc::new(TokenFile::load_or_generate(&token_file)?);
 
-    // reading the request
-    stream.read(&mut buffer).unwrap();
+    let mut scheduler = Scheduler::new(max_active_downloads as usize, download_path);
+    scheduler.set_retention(retention)?;
+    scheduler.client_factory = ClientFactory::new(client);
 
-    let mngr = String::from_utf8_lossy(&buffer).to_string();
-    let mut headers = [httparse::EMPTY_HEADER; 16];
-
-    // parse the headers
-    let mut req = httparse::Request::new(&mut headers);
-    let _http_req = req.parse(&buffer[..]);
-
-    // check the method
-    if req.method != Some("POST") {
-        let _ = stream.write_all("HTTP/1.1 405 Method Not Allowed\r\n\r\n\r\n".as_bytes());
-        return;
+    // continue the jobs the last shutdown saved, the manager doesn't start without them
+    let restored = scheduler.restore_state()?;
+    if restored > 0 {
+        println!("Restored {} jobs", restored);
     }
 
-    // if req.path == Some("/help") {
-
-    // }
+    // the communication channel to the shceduler main thread
+    // the server sends its commands on the events channel of the scheduler
+    let (server_tx, events_rx) = mpsc::channel(EVENTS_CAPACITY);
+
+    let app_data = web::Data::new(AppData {
+        server_tx: server_tx.clone(),
+        reply_timeout: Duration::from_secs(10),
+    });
+
+    // the signals are handled below, so the scheduler can save its jobs before the server stops
+    // every request is authorized by its token before it reaches the routes, they get its scope
+    let max_body_size = options.max_body_size();
+    let read_timeout = options.read_timeout();
+    let serve_rpc = options.rpc;
+    let mut server = HttpServer::new({
+        let app_data = app_data.clone();
+        move || {
+            let tokens = tokens.clone();
+            App::new()
+                .app_data(app_data.clone())
+                .app_data(web::Data::from(tokens.clone()))
+                .app_data(ReadTimeout(read_timeout))
+                .app_data(request::json_config(max_body_size))
+                .app_data(request::query_config())
+                .app_data(request::path_config())
+                .wrap_fn(move |req, srv| {
+                    let authorized = tokens.authorize(&req).map(|scope| {
+                        if let Some(scope) = scope {
+                            req.extensions_mut().insert(scope);
+                        }
+                        srv.call(req)
+                    });
+                    async move { authorized?.await }
+                })
+                .configure(routes::configure)
+                .configure(dashboard::configure)
+                .configure(|cfg| {
+                    if serve_rpc {
+                        rpc::configure(cfg, max_body_size);
+                    }
+                })
+        }
+    })
+    .on_connect(mark_connection)
+    .client_request_timeout(read_timeout)
+    .disable_signals();
+
+    // every address is bound before the scheduler runs, a wrong one stops the start
+    // a host name binds all of its addresses, like localhost on both ipv4 and ipv6
+    for address in &listen {
+        let bound = if https {
+            server.bind_openssl(address.as_str(), tls.acceptor()?)
+        } else {
+            server.bind(address.as_str())
+        };
+        server = bound.map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", address, e))?;
+    }
+    for (address, scheme) in server.addrs_with_scheme() {
+        println!("Listening on {}://{}", scheme, address);
+    }
+    if let Some(path) = &socket_path {
+        #[cfg(unix)]
+        {
+            server = server
+                .listen_uds(socket::listen(path)?)
+                .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", path.display(), e))?;
+        }
+        #[cfg(not(unix))]
+        return Err(anyhow::anyhow!("There are no unix sockets on this platform, {} can't be used", path.display()));
+
+        println!("Listening on {}", path.display());
+    }
 
-    // check the path
-    if req.path != Some("/command") {
-        let _ = stream.write_all("HTTP/1.1 404 Not Found\r\n\r\n\r\n".as_bytes());
-        return;
+    // the saved jobs stay on the disk until the server listens, a start that fails keeps them for the next one
+    scheduler.consume_saved_state()?;
+
+    let server = server.run();
+    let server_handle = server.handle();
+    let mut server = tokio::spawn(server);
+
+    // running the scheduler until it is shut down
+    let mut scheduler_task = Scheduler::run(scheduler, events_rx, server_tx);
+
+    let mut signals = ShutdownSignals::new();
+    let mut signaled = false;
+
+    // serve the requests until the scheduler stops
+    loop {
+        tokio::select! {
+            _ = signals.recv() => {
+                // the first signal waits for the active downloads, the second one pauses them
+                let shutdown = ManagerCommands::Shutdown { timeout: None, pause: signaled };
+                signaled = true;
+                shutdown_on_signal(shutdown, &app_data).await;
+            }
+            _ = &mut scheduler_task => {
+                // the requests in flight get their answers, like the one of the Shutdown command
+                server_handle.stop(true).await;
+                break;
+            }
+            _ = &mut server => break,
+        }
     }
 
-    // extract the body
-    let mngr = if let Some(mg) = mngr.rsplit_once("\r\n\r\n") {
-        mg.1.to_string()
-    } else {
-        mngr
-    };
+    if let Some(path) = &socket_path {
+        remove_socket(path);
+    }
 
-    // remove the trailing redundunt characters (nulls)
-    let mngr = mngr.split_once("\u{0}");
+    Ok(())
+}
 
-    // return bad request if there are no body
-    if mngr.is_none() {
-        let _ = stream.write_all("HTTP/1.1 400 Bad Request\r\n\r\n\r\n".as_bytes());
-        return;
-    }
+// the server marks the connections of the unix socket, the authorization lets them through
+fn mark_connection(conn: &dyn Any, ext: &mut Extensions) {
+    #[cfg(unix)]
+    socket::mark_local(conn, ext);
+    #[cfg(not(unix))]
+    let _ = (conn, ext);
+}
 
-    // deserilize the manager (which holds the commands)
-    let mngr = serde_json::from_str::<Manager>(mngr.unwrap().0);
+fn remove_socket(path: &Path) {
+    #[cfg(unix)]
+    socket::remove(path);
+    #[cfg(not(unix))]
+    let _ = path;
+}
 
-    if mngr.is_err() {
-        let _ = stream.write_all("HTTP/1.1 400 Bad Request\r\n\r\n\r\n".as_bytes());
-        return;
+// send the Shutdown command of a signal to the scheduler and print its answer
+async fn shutdown_on_signal(shutdown: ManagerCommands, app_data: &AppData<SchedulerEvent>) {
+    let mngr = Manager {
+        subcommands: shutdown,
+        json: false,
+        server: None,
+        socket: None,
+        tls: Default::default(),
+        token_file: None,
+        token: None,
+    };
+    match app_data.ask(|reply| SchedulerEvent::Command(Box::new(mngr), reply)).await {
+        Ok(Ok(ResponseBody::Message { message })) => println!("{}", message),
+        Ok(Err(e)) => println!("Error: {}", e),
+        _ => (),
     }
+}
 
-    let mngr = mngr.unwrap();
-
-    // send the command (manager) to the scheduler
-    let _ = app_data.server_tx.send(mngr);
+// SIGINT (ctrl+c) and SIGTERM stop the manager, the other platforms have only ctrl+c
+struct ShutdownSignals {
+    #[cfg(unix)]
+    interrupt: Option<Signal>,
+    #[cfg(unix)]
+    terminate: Option<Signal>,
+}
 
-    // recev a message (command result)
-    let rx = app_data.server_rx.recv().unwrap();
+impl ShutdownSignals {
+    fn new() -> Self {
+        ShutdownSignals {
+            #[cfg(unix)]
+            interrupt: signal(SignalKind::interrupt()).ok(),
+            #[cfg(unix)]
+            terminate: signal(SignalKind::terminate()).ok(),
+        }
+    }
 
-    // form the http response (a request line and body [no headers])
-    let mut string = String::from("HTTP/1.1 200 Ok\r\n\r\n");
+    #[cfg(unix)]
+    async fn recv(&mut self) {
+        // a signal that couldn't be registered never fires
+        async fn recv_or_wait(signal: &mut Option<Signal>) {
+            match signal {
+                Some(signal) => {
+                    signal.recv().await;
+                }
+                None => std::future::pending().await,
+            }
+        }
+
+        tokio::select! {
+            _ = recv_or_wait(&mut self.interrupt) => (),
+            _ = recv_or_wait(&mut self.terminate) => (),
+        }
+    }
 
-    for element in rx {
-        string.push_str(element.as_str());
+    #[cfg(not(unix))]
+    async fn recv(&mut self) {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
     }
-    let _ = stream.write_all(&string.as_bytes());
 }
@@ -1,4 +1,16 @@
 mod executor;
 mod utils;
+mod routes;
+mod events;
+mod request;
+mod dashboard;
+mod rpc;
+mod auth;
+mod tls;
+#[cfg(unix)]
+mod socket;
 
-pub use executor::*;
\ No newline at end of file
+pub use executor::*;
+pub use auth::TokenFile;
+#[cfg(unix)]
+pub use socket::{default_path as default_socket_path, in_use as socket_in_use, post as post_over_socket};
\ No newline at end of file
@@ -0,0 +1,85 @@
+use actix_web::dev::Payload;
+use actix_web::error::JsonPayloadError;
+use actix_web::{web, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+use crate::commands::ServerOptions;
+use crate::scheduler::{ApiError, ErrorCode};
+
+/*
+ * What the server reads from its clients
+ *
+ * - actix-web serves the connections on its workers, a slow client only holds its own connection
+ * - The headers have to arrive within the read timeout, else the connection is answered 408 and closed
+ * - The json bodies are read up to their Content-Length, a body over the limit is refused before it is read
+ * - The body has to arrive within the read timeout too, the timeout starts again after the headers
+ * - The bodies, the queries and the paths that can't be read are answered with the json errors of the api
+ */
+
+pub const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024;
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl ServerOptions {
+    pub fn max_body_size(&self) -> usize {
+        self.max_body_size.unwrap_or(DEFAULT_MAX_BODY_SIZE)
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        self.read_timeout.map(Duration::from_secs).unwrap_or(DEFAULT_READ_TIMEOUT)
+    }
+}
+
+// the read timeout of the bodies, it is in the app data of every worker
+#[derive(Debug, Clone, Copy)]
+pub struct ReadTimeout(pub Duration);
+
+// a json body read within the read timeout, web::Json reads it with the limit of the json config
+pub struct JsonBody<T>(pub T);
+
+impl<T> JsonBody<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for JsonBody<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let read_timeout = req.app_data::<ReadTimeout>().map_or(DEFAULT_READ_TIMEOUT, |timeout| timeout.0);
+        let json = web::Json::<T>::from_request(req, payload);
+
+        Box::pin(async move {
+            match tokio::time::timeout(read_timeout, json).await {
+                Ok(json) => Ok(JsonBody(json?.into_inner())),
+                Err(_) => Err(ApiError::new(ErrorCode::RequestTimeout, "The body of the request was not received in time").into()),
+            }
+        })
+    }
+}
+
+pub fn json_config(max_body_size: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(max_body_size)
+        .error_handler(|e, _| {
+            let error = match e {
+                JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => {
+                    ApiError::new(ErrorCode::PayloadTooLarge, e)
+                }
+                JsonPayloadError::ContentType => ApiError::invalid("The body of the request has to be application/json"),
+                e => ApiError::invalid(e),
+            };
+            error.into()
+        })
+}
+
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|e, _| ApiError::invalid(e).into())
+}
+
+pub fn path_config() -> web::PathConfig {
+    web::PathConfig::default().error_handler(|e, _| ApiError::invalid(e).into())
+}
@@ -0,0 +1,207 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+use actix_web::http::StatusCode;
+use actix_web::ResponseError;
+use serde::Deserialize;
+
+use crate::commands::{AddOptions, Manager, ManagerCommands};
+use crate::scheduler::{ApiError, ErrorCode, JobRequest, Reply, Response, SchedulerEvent, State};
+
+use super::auth::{Scope, TokenFile};
+use super::events::{self, EventsQuery};
+use super::request::JsonBody;
+use super::utils::{AppData, AskError};
+
+/*
+ * The REST resources of the server
+ *
+ * - POST /jobs adds a job, GET /jobs lists the jobs (?state= filters them), GET /jobs/{id} returns one job
+ * - DELETE /jobs/{id} drops a job, an active one is stopped first (?delete_file=true deletes its file too)
+ * - POST /jobs/{id}/cancel stops a job, it stays in the list as failed until it is retried or dropped
+ * - POST /jobs/{id}/pause and POST /jobs/{id}/resume pause and resume a job
+ * - POST /jobs/{id}/retry moves a failed job back to the waiting list
+ * - POST /jobs/{id}/move moves a waiting job to another position of the waiting list
+ * - GET /metrics returns the metrics of the scheduler in the text format of Prometheus
+ * - GET /events streams the events of the jobs as server-sent events (?job= and ?queue= filter them)
+ * - POST /command takes the commands of the cli, the cli renders the response itself
+ * - Everything is answered with a versioned Response, the errors with their code and its status code
+ * - The token of the request is checked before the routes, a read token can only run the commands that read
+ */
+
+type Data = web::Data<AppData<SchedulerEvent>>;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_job)
+        .service(list_jobs)
+        .service(get_job)
+        .service(delete_job)
+        .service(cancel_job)
+        .service(pause_job)
+        .service(resume_job)
+        .service(retry_job)
+        .service(move_job)
+        .service(job_events)
+        .service(metrics)
+        .service(command);
+}
+
+#[derive(Debug, Deserialize)]
+struct ListQuery {
+    state: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteQuery {
+    #[serde(default)]
+    delete_file: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct MoveOptions {
+    position: usize,
+}
+
+#[post("/jobs")]
+async fn create_job(data: Data, options: JsonBody<AddOptions>) -> HttpResponse {
+    let result = ask_job(&data, JobRequest::Add(options.into_inner())).await;
+    respond(result, HttpResponse::Created())
+}
+
+#[get("/jobs")]
+async fn list_jobs(data: Data, query: web::Query<ListQuery>) -> HttpResponse {
+    let state = match query.state.as_deref().map(State::parse).transpose() {
+        Ok(state) => state,
+        Err(e) => return error(ApiError::invalid(e)),
+    };
+
+    let result = ask_job(&data, JobRequest::List { state }).await;
+    respond(result, HttpResponse::Ok())
+}
+
+#[get("/jobs/{id}")]
+async fn get_job(data: Data, id: web::Path<usize>) -> HttpResponse {
+    let result = ask_job(&data, JobRequest::Get(id.into_inner())).await;
+    respond(result, HttpResponse::Ok())
+}
+
+#[delete("/jobs/{id}")]
+async fn delete_job(data: Data, id: web::Path<usize>, query: web::Query<DeleteQuery>) -> HttpResponse {
+    let request = JobRequest::Delete {
+        id: id.into_inner(),
+        delete_file: query.delete_file,
+    };
+    let result = ask_job(&data, request).await;
+    respond(result, HttpResponse::Ok())
+}
+
+#[post("/jobs/{id}/cancel")]
+async fn cancel_job(data: Data, id: web::Path<usize>, query: web::Query<DeleteQuery>) -> HttpResponse {
+    let request = JobRequest::Cancel {
+        id: id.into_inner(),
+        delete_file: query.delete_file,
+    };
+    let result = ask_job(&data, request).await;
+    respond(result, HttpResponse::Ok())
+}
+
+#[post("/jobs/{id}/pause")]
+async fn pause_job(data: Data, id: web::Path<usize>) -> HttpResponse {
+    let result = ask_job(&data, JobRequest::Pause(id.into_inner())).await;
+    respond(result, HttpResponse::Ok())
+}
+
+#[post("/jobs/{id}/resume")]
+async fn resume_job(data: Data, id: web::Path<usize>) -> HttpResponse {
+    let result = ask_job(&data, JobRequest::Resume(id.into_inner())).await;
+    respond(result, HttpResponse::Ok())
+}
+
+#[post("/jobs/{id}/retry")]
+async fn retry_job(data: Data, id: web::Path<usize>) -> HttpResponse {
+    let result = ask_job(&data, JobRequest::Retry(id.into_inner())).await;
+    respond(result, HttpResponse::Ok())
+}
+
+#[post("/jobs/{id}/move")]
+async fn move_job(data: Data, id: web::Path<usize>, options: JsonBody<MoveOptions>) -> HttpResponse {
+    let request = JobRequest::Move {
+        id: id.into_inner(),
+        position: options.into_inner().position,
+    };
+    let result = ask_job(&data, request).await;
+    respond(result, HttpResponse::Ok())
+}
+
+// the stream starts with the events after the subscription, the state before it is in GET /jobs
+#[get("/events")]
+async fn job_events(data: Data, query: web::Query<EventsQuery>) -> HttpResponse {
+    match data.ask(SchedulerEvent::Subscribe).await {
+        Ok(receiver) => HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header((actix_web::http::header::CACHE_CONTROL, "no-cache"))
+            .streaming(events::stream(receiver, query.into_inner())),
+        Err(e) => error(unanswered(e)),
+    }
+}
+
+// a read token is enough to scrape them, Prometheus sends it with its bearer_token setting
+#[get("/metrics")]
+async fn metrics(data: Data) -> HttpResponse {
+    match data.ask(SchedulerEvent::Metrics).await {
+        Ok(metrics) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4; charset=utf-8")
+            .body(metrics),
+        Err(e) => error(unanswered(e)),
+    }
+}
+
+// the commands of the cli, the answer is the same response as the one of the resources
+#[post("/command")]
+async fn command(data: Data, scope: web::ReqData<Scope>, mngr: JsonBody<Manager>) -> HttpResponse {
+    let mngr = mngr.into_inner();
+    if *scope == Scope::Read && !is_read_only(&mngr.subcommands) {
+        return error(TokenFile::forbidden());
+    }
+    let result = data.ask(|reply| SchedulerEvent::Command(Box::new(mngr), reply)).await;
+    respond(result, HttpResponse::Ok())
+}
+
+// the commands a read token can run
+fn is_read_only(subcommands: &ManagerCommands) -> bool {
+    matches!(subcommands, ManagerCommands::List { .. } | ManagerCommands::Info { .. })
+}
+
+async fn ask_job(data: &Data, request: JobRequest) -> Result<Reply, AskError> {
+    data.ask(|reply| SchedulerEvent::Job(request, reply)).await
+}
+
+// the success status is given by the resource, the errors decide their own
+fn respond(result: Result<Reply, AskError>, mut success: actix_web::HttpResponseBuilder) -> HttpResponse {
+    match result {
+        Ok(Ok(body)) => success.json(Response::new(body)),
+        Ok(Err(e)) => error(e),
+        Err(e) => error(unanswered(e)),
+    }
+}
+
+fn error(e: ApiError) -> HttpResponse {
+    e.error_response()
+}
+
+// the errors returned before the routes, like the ones of the tokens, are answered the same way
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.code.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(Response::from(Err(self.clone())))
+    }
+}
+
+// if the scheduler dropped the reply channel without answering it is not able to serve
+pub fn unanswered(e: AskError) -> ApiError {
+    match e {
+        AskError::Unavailable => ApiError::new(ErrorCode::Unavailable, "The scheduler is not running"),
+        AskError::Timeout => ApiError::new(ErrorCode::Timeout, "The scheduler didn't answer in time"),
+    }
+}
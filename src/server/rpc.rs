@@ -0,0 +1,520 @@
+use actix_web::http::{header, Method};
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::commands::AddOptions;
+use crate::scheduler::{DownloadError, JobRequest, JobView, ResponseBody, SchedulerEvent, State};
+
+use super::auth::{Scope, TokenFile};
+use super::request::{json_config, JsonBody};
+use super::routes::unanswered;
+use super::utils::AppData;
+
+/*
+ * The aria2 compatible JSON-RPC interface, served on POST /jsonrpc when start is given --rpc
+ *
+ * - The tools that speak to aria2 can add and watch the jobs of the manager with the same calls
+ * - The gid of a job is its id as 16 hex digits, like 000000000000000c for the job 12
+ * - A call sends its token as its first param, "token:<token>" like the --rpc-secret of aria2,
+ *   or the request sends it as a bearer token like the REST resources; a read token can only call the tell methods
+ * - aria2.addUri takes the options dir, out, header, all-proxy (or http-proxy) and check-certificate,
+ *   the other options are ignored and only the first uri is downloaded, the rest are mirrors for aria2
+ * - aria2.remove cancels the job, it is removed like in aria2 until removeDownloadResult drops it
+ *   its partial file stays on the disk
+ * - The jobs that are done or not modified are complete, the failed and the blocked ones are error
+ * - The numbers are strings like the ones of aria2, the browsers of other origins may call it (cors)
+ */
+
+pub const RPC_PATH: &str = "/jsonrpc";
+
+// the code of the errors of the calls themselves, the protocol errors have the codes of JSON-RPC
+const ARIA2_ERROR: i64 = 1;
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+const METHODS: [&str; 15] = [
+    "aria2.addUri",
+    "aria2.remove",
+    "aria2.forceRemove",
+    "aria2.pause",
+    "aria2.forcePause",
+    "aria2.unpause",
+    "aria2.removeDownloadResult",
+    "aria2.tellStatus",
+    "aria2.tellActive",
+    "aria2.tellWaiting",
+    "aria2.tellStopped",
+    "aria2.getGlobalStat",
+    "aria2.getVersion",
+    "system.multicall",
+    "system.listMethods",
+];
+
+// the methods a read token can call
+const READ_METHODS: [&str; 8] = [
+    "aria2.tellStatus",
+    "aria2.tellActive",
+    "aria2.tellWaiting",
+    "aria2.tellStopped",
+    "aria2.getGlobalStat",
+    "aria2.getVersion",
+    "system.multicall",
+    "system.listMethods",
+];
+
+type Data = web::Data<AppData<SchedulerEvent>>;
+
+// the clients of aria2 don't always send a content type, so the body is taken as JSON whatever it says
+pub fn configure(cfg: &mut web::ServiceConfig, max_body_size: usize) {
+    cfg.service(
+        web::resource(RPC_PATH)
+            .app_data(
+                json_config(max_body_size)
+                    .content_type_required(false)
+                    .content_type(|_| true),
+            )
+            .route(web::post().to(jsonrpc))
+            .route(web::method(Method::OPTIONS).to(preflight)),
+    );
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    method: String,
+    #[serde(default)]
+    params: Vec<Value>,
+    #[serde(default)]
+    id: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcResponse {
+    id: Value,
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        RpcResponse {
+            id,
+            jsonrpc: "2.0",
+            result,
+            error,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        RpcError {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn aria2(message: impl ToString) -> Self {
+        Self::new(ARIA2_ERROR, message)
+    }
+
+    fn params(message: impl ToString) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+}
+
+// the one who calls: the scope of its bearer token if it sent one, else the token of every call decides
+struct Caller {
+    data: Data,
+    tokens: web::Data<TokenFile>,
+    scope: Option<Scope>,
+}
+
+// a request is one call or a batch of calls
+async fn jsonrpc(
+    data: Data,
+    tokens: web::Data<TokenFile>,
+    scope: Option<web::ReqData<Scope>>,
+    body: Result<JsonBody<Value>, actix_web::Error>,
+) -> HttpResponse {
+    let body = match body {
+        Ok(body) => body.into_inner(),
+        Err(e) => return reply(RpcResponse::new(Value::Null, Err(RpcError::new(PARSE_ERROR, e)))),
+    };
+    let caller = Caller {
+        data,
+        tokens,
+        scope: scope.map(|scope| *scope),
+    };
+
+    match body {
+        Value::Array(calls) if !calls.is_empty() => {
+            let mut responses = Vec::with_capacity(calls.len());
+            for call in calls {
+                responses.push(caller.request(call).await);
+            }
+            reply(responses)
+        }
+        call => reply(caller.request(call).await),
+    }
+}
+
+// the browsers ask before they send a call from another origin
+async fn preflight() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+        .insert_header((header::ACCESS_CONTROL_ALLOW_METHODS, "POST, OPTIONS"))
+        .insert_header((header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, Authorization"))
+        .insert_header((header::ACCESS_CONTROL_MAX_AGE, "1728000"))
+        .finish()
+}
+
+fn reply(body: impl Serialize) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+        .json(body)
+}
+
+impl Caller {
+    async fn request(&self, call: Value) -> RpcResponse {
+        match serde_json::from_value::<RpcRequest>(call) {
+            Ok(request) => {
+                let result = self.call(&request.method, request.params).await;
+                RpcResponse::new(request.id, result)
+            }
+            Err(e) => RpcResponse::new(Value::Null, Err(RpcError::new(INVALID_REQUEST, e))),
+        }
+    }
+
+    async fn call(&self, method: &str, mut params: Vec<Value>) -> Result<Value, RpcError> {
+        if !METHODS.contains(&method) {
+            return Err(RpcError::new(METHOD_NOT_FOUND, format!("No such method: {}", method)));
+        }
+        // the calls of a multicall carry their own tokens
+        if method != "system.multicall" && method != "system.listMethods" {
+            self.authorize(method, &mut params)?;
+        }
+
+        match method {
+            "aria2.addUri" => self.add_uri(&params).await,
+            "aria2.remove" | "aria2.forceRemove" => {
+                let id = gid_param(&params, 0)?;
+                self.ask(JobRequest::Cancel { id, delete_file: false }).await?;
+                Ok(gid(id))
+            }
+            "aria2.removeDownloadResult" => {
+                let id = gid_param(&params, 0)?;
+                if !matches!(status(&self.job(id).await?), "complete" | "error" | "removed") {
+                    return Err(RpcError::aria2(format!("Could not remove download result of GID#{}", gid_str(id))));
+                }
+                self.ask(JobRequest::Delete { id, delete_file: false }).await?;
+                Ok(json!("OK"))
+            }
+            "aria2.pause" | "aria2.forcePause" => {
+                let id = gid_param(&params, 0)?;
+                self.ask(JobRequest::Pause(id)).await?;
+                Ok(gid(id))
+            }
+            "aria2.unpause" => {
+                let id = gid_param(&params, 0)?;
+                self.ask(JobRequest::Resume(id)).await?;
+                Ok(gid(id))
+            }
+            "aria2.tellStatus" => {
+                let id = gid_param(&params, 0)?;
+                let keys = keys_param(&params, 1)?;
+                Ok(status_of(&self.job(id).await?, &keys))
+            }
+            "aria2.tellActive" => {
+                let keys = keys_param(&params, 0)?;
+                let jobs = self.jobs().await?.into_iter().filter(|job| job.state == State::Active);
+                Ok(jobs.map(|job| status_of(&job, &keys)).collect())
+            }
+            "aria2.tellWaiting" | "aria2.tellStopped" => {
+                let statuses: &[&str] = if method == "aria2.tellWaiting" {
+                    &["waiting", "paused"]
+                } else {
+                    &["complete", "error", "removed"]
+                };
+                let offset = int_param(&params, 0)?;
+                let num = int_param(&params, 1)?;
+                let keys = keys_param(&params, 2)?;
+                let jobs: Vec<JobView> = self
+                    .jobs()
+                    .await?
+                    .into_iter()
+                    .filter(|job| statuses.contains(&status(job)))
+                    .collect();
+                Ok(page(jobs, offset, num).iter().map(|job| status_of(job, &keys)).collect())
+            }
+            "aria2.getGlobalStat" => {
+                let jobs = self.jobs().await?;
+                let count = |states: &[State]| jobs.iter().filter(|job| states.contains(&job.state)).count();
+                let stopped = count(&[State::Done, State::NotModified, State::Failed, State::Blocked]);
+                let speed: f64 = jobs.iter().filter_map(|job| job.speed).sum();
+                Ok(json!({
+                    "downloadSpeed": (speed as u64).to_string(),
+                    "uploadSpeed": "0",
+                    "numActive": count(&[State::Active]).to_string(),
+                    "numWaiting": count(&[State::Waiting, State::Paused]).to_string(),
+                    "numStopped": stopped.to_string(),
+                    "numStoppedTotal": stopped.to_string(),
+                }))
+            }
+            "aria2.getVersion" => Ok(json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "enabledFeatures": ["HTTPS"],
+            })),
+            "system.listMethods" => Ok(json!(METHODS)),
+            "system.multicall" => self.multicall(params).await,
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("No such method: {}", method))),
+        }
+    }
+
+    // a token in the params is taken out of them, else the scope of the bearer token is used
+    fn authorize(&self, method: &str, params: &mut Vec<Value>) -> Result<(), RpcError> {
+        let secret = match params.first().and_then(Value::as_str) {
+            Some(token) if token.starts_with("token:") => Some(params.remove(0)),
+            _ => None,
+        };
+
+        let scope = match secret.as_ref().and_then(Value::as_str) {
+            Some(token) => self.tokens.scope_of(token.trim_start_matches("token:")),
+            None => self.scope,
+        };
+        match scope {
+            Some(Scope::Full) => Ok(()),
+            Some(Scope::Read) if READ_METHODS.contains(&method) => Ok(()),
+            Some(Scope::Read) => Err(RpcError::aria2(TokenFile::forbidden().message)),
+            None => Err(RpcError::aria2("Unauthorized")),
+        }
+    }
+
+    // every call of a multicall is answered in an array of one result, or with its error
+    async fn multicall(&self, params: Vec<Value>) -> Result<Value, RpcError> {
+        let calls = match params.into_iter().next() {
+            Some(Value::Array(calls)) => calls,
+            _ => return Err(RpcError::params("system.multicall takes an array of calls")),
+        };
+
+        let mut results = Vec::with_capacity(calls.len());
+        for call in calls {
+            let method = call.get("methodName").and_then(Value::as_str).unwrap_or_default();
+            let params = match call.get("params") {
+                Some(Value::Array(params)) => params.clone(),
+                _ => vec![],
+            };
+            let result = match method {
+                "system.multicall" => Err(RpcError::aria2("Recursive system.multicall forbidden.")),
+                method => Box::pin(self.call(method, params)).await,
+            };
+            results.push(match result {
+                Ok(result) => json!([result]),
+                Err(error) => json!(error),
+            });
+        }
+
+        Ok(Value::Array(results))
+    }
+
+    async fn add_uri(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let url = params
+            .first()
+            .and_then(Value::as_array)
+            .and_then(|uris| uris.first())
+            .and_then(Value::as_str)
+            .ok_or_else(|| RpcError::params("aria2.addUri takes an array of uris"))?;
+        let options = match params.get(1) {
+            Some(Value::Object(options)) => options.clone(),
+            None | Some(Value::Null) => Map::new(),
+            Some(_) => return Err(RpcError::params("The options of aria2.addUri are an object")),
+        };
+        let option = |name: &str| options.get(name).and_then(Value::as_str).map(String::from);
+
+        // a header is given as a string, or many of them as an array
+        let headers = match options.get("header") {
+            Some(Value::String(header)) => vec![header.clone()],
+            Some(Value::Array(headers)) => headers.iter().filter_map(Value::as_str).map(String::from).collect(),
+            _ => vec![],
+        };
+
+        let options = AddOptions {
+            url: url.to_string(),
+            custom_name: option("out"),
+            custom_download_path: option("dir"),
+            proxy: option("all-proxy").or_else(|| option("http-proxy")),
+            insecure: option("check-certificate").as_deref() == Some("false"),
+            headers,
+            ..Default::default()
+        };
+
+        let id = match self.ask(JobRequest::Add(options)).await? {
+            ResponseBody::Job { job } => job.id,
+            _ => return Err(RpcError::aria2("The scheduler didn't return the added job")),
+        };
+        if let Some(position) = params.get(2).and_then(Value::as_u64) {
+            self.ask(JobRequest::Move { id, position: position as usize }).await?;
+        }
+
+        Ok(gid(id))
+    }
+
+    async fn ask(&self, request: JobRequest) -> Result<ResponseBody, RpcError> {
+        match self.data.ask(|reply| SchedulerEvent::Job(request, reply)).await {
+            Ok(Ok(body)) => Ok(body),
+            Ok(Err(e)) => Err(RpcError::aria2(e.message)),
+            Err(e) => Err(RpcError::aria2(unanswered(e).message)),
+        }
+    }
+
+    async fn job(&self, id: usize) -> Result<JobView, RpcError> {
+        match self.ask(JobRequest::Get(id)).await {
+            Ok(ResponseBody::Job { job }) => Ok(*job),
+            Ok(_) => Err(RpcError::aria2("The scheduler didn't return the job")),
+            Err(_) => Err(RpcError::aria2(format!("GID {} is not found", gid_str(id)))),
+        }
+    }
+
+    // the jobs in the order of the scheduler, the waiting list first in the order the jobs start
+    async fn jobs(&self) -> Result<Vec<JobView>, RpcError> {
+        match self.ask(JobRequest::List { state: None }).await? {
+            ResponseBody::Jobs { jobs, .. } => Ok(jobs),
+            _ => Err(RpcError::aria2("The scheduler didn't return the jobs")),
+        }
+    }
+}
+
+fn gid_str(id: usize) -> String {
+    format!("{:016x}", id)
+}
+
+fn gid(id: usize) -> Value {
+    Value::String(gid_str(id))
+}
+
+fn gid_param(params: &[Value], index: usize) -> Result<usize, RpcError> {
+    let gid = params
+        .get(index)
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::params("The call takes a GID"))?;
+    usize::from_str_radix(gid, 16).map_err(|_| RpcError::aria2(format!("Bad GID {}", gid)))
+}
+
+fn int_param(params: &[Value], index: usize) -> Result<i64, RpcError> {
+    params
+        .get(index)
+        .and_then(Value::as_i64)
+        .ok_or_else(|| RpcError::params("The call takes an offset and a number of jobs"))
+}
+
+// the keys of the status to return, all of them if there are none
+fn keys_param(params: &[Value], index: usize) -> Result<Vec<String>, RpcError> {
+    match params.get(index) {
+        None | Some(Value::Null) => Ok(vec![]),
+        Some(Value::Array(keys)) => Ok(keys.iter().filter_map(Value::as_str).map(String::from).collect()),
+        Some(_) => Err(RpcError::params("The keys are an array of strings")),
+    }
+}
+
+// a negative offset counts from the end of the list and goes backwards, like aria2 does
+fn page(jobs: Vec<JobView>, offset: i64, num: i64) -> Vec<JobView> {
+    let num = num.max(0) as usize;
+    if offset >= 0 {
+        jobs.into_iter().skip(offset as usize).take(num).collect()
+    } else {
+        let skip = (-offset - 1) as usize;
+        jobs.into_iter().rev().skip(skip).take(num).collect()
+    }
+}
+
+fn status(job: &JobView) -> &'static str {
+    match job.state {
+        State::Active => "active",
+        State::Waiting => "waiting",
+        State::Paused => "paused",
+        State::Done | State::NotModified => "complete",
+        State::Failed if matches!(job.error, Some(DownloadError::Cancelled { .. })) => "removed",
+        State::Failed | State::Blocked => "error",
+    }
+}
+
+// the exit status aria2 gives to the same error
+fn error_code(error: &DownloadError) -> u8 {
+    match error {
+        DownloadError::Timeout { .. } => 2,
+        DownloadError::HttpStatus { status: 404, .. } => 3,
+        DownloadError::HttpStatus { status: 401 | 403, .. } => 24,
+        DownloadError::HttpStatus { status: 503, .. } => 29,
+        DownloadError::HttpStatus { .. } => 22,
+        DownloadError::Connect { .. } | DownloadError::Tls { .. } => 6,
+        DownloadError::DiskFull { .. } => 9,
+        DownloadError::Io { .. } => 17,
+        DownloadError::Dns { .. } => 19,
+        DownloadError::InvalidRequest { .. } => 28,
+        DownloadError::Checksum { .. } => 32,
+        DownloadError::Cancelled { .. } | DownloadError::Blocked { .. } | DownloadError::Internal { .. } => 1,
+    }
+}
+
+// the status of a job with the keys of aria2, only the asked keys if there are any
+fn status_of(job: &JobView, keys: &[String]) -> Value {
+    let completed = job.final_size.unwrap_or(job.downloaded_size);
+    let total = job.final_size.or(job.total_size).unwrap_or(0);
+    // the bytes of a resumed partial file aren't part of the speed
+    let speed = job.speed.unwrap_or(0.0) as u64;
+
+    let mut view = json!({
+        "gid": gid_str(job.id),
+        "status": status(job),
+        "totalLength": total.to_string(),
+        "completedLength": completed.to_string(),
+        "uploadLength": "0",
+        "downloadSpeed": speed.to_string(),
+        "uploadSpeed": "0",
+        "connections": if job.state == State::Active { "1" } else { "0" },
+        "dir": job.download_path,
+        "files": [{
+            "index": "1",
+            "path": format!("{}/{}", job.download_path, job.name),
+            "length": total.to_string(),
+            "completedLength": completed.to_string(),
+            "selected": "true",
+            "uris": [{ "uri": job.url, "status": "used" }],
+        }],
+    });
+
+    let object = match view.as_object_mut() {
+        Some(object) => object,
+        None => return view,
+    };
+    match &job.error {
+        Some(error) => {
+            object.insert(String::from("errorCode"), json!(error_code(error).to_string()));
+            object.insert(String::from("errorMessage"), json!(error.to_string()));
+        }
+        None if status(job) == "complete" => {
+            object.insert(String::from("errorCode"), json!("0"));
+        }
+        None => (),
+    }
+    if !keys.is_empty() {
+        object.retain(|key, _| keys.contains(key));
+    }
+
+    view
+}
@@ -0,0 +1,86 @@
+use actix_web::dev::Extensions;
+use actix_web::rt::net::UnixStream;
+use reqwest::StatusCode;
+use std::any::Any;
+use std::fs;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+
+use super::auth::TokenFile;
+
+/*
+ * The unix socket of the control server
+ *
+ * - Start listens on it next to its tcp addresses, with --no-tcp it listens only on the socket
+ * - A start without --socket skips the default socket if another manager listens on it, so many managers can run
+ * - The socket carries the same http requests and json responses as the tcp addresses
+ * - Only the owner can open the directory of the socket and connect to it, so its requests need no token
+ * - The cli sends its commands to the socket if it exists and no --server is given
+ */
+
+// the connections of the socket are marked by the server, the authorization lets them through
+pub struct LocalConnection;
+
+// $XDG_RUNTIME_DIR/manager/manager.sock, else next to the token file
+pub fn default_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("manager").join("manager.sock"),
+        None => TokenFile::default_path().with_file_name("manager.sock"),
+    }
+}
+
+// a socket left by a manager that didn't stop cleanly is replaced, one that still answers is not
+pub fn listen(path: &Path) -> anyhow::Result<UnixListener> {
+    let fail = |e: std::io::Error| anyhow::anyhow!("Failed to listen on {}: {}", path.display(), e);
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::DirBuilder::new().recursive(true).mode(0o700).create(dir).map_err(fail)?;
+    }
+    if path.exists() {
+        if in_use(path) {
+            return Err(anyhow::anyhow!("Another manager listens on {}", path.display()));
+        }
+        fs::remove_file(path).map_err(fail)?;
+    }
+
+    let listener = UnixListener::bind(path).map_err(fail)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(fail)?;
+
+    Ok(listener)
+}
+
+// a manager answers on the socket
+pub fn in_use(path: &Path) -> bool {
+    std::os::unix::net::UnixStream::connect(path).is_ok()
+}
+
+pub fn remove(path: &Path) {
+    let _ = fs::remove_file(path);
+}
+
+// called by the server for every new connection
+pub fn mark_local(conn: &dyn Any, ext: &mut Extensions) {
+    if conn.downcast_ref::<UnixStream>().is_some() {
+        ext.insert(LocalConnection);
+    }
+}
+
+// the cli posts its json to the socket with a single http/1 connection
+pub async fn post(path: &Path, uri: &str, body: String) -> anyhow::Result<(StatusCode, String)> {
+    let stream = tokio::net::UnixStream::connect(path)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to connect to {}: {}", path.display(), e))?;
+    let (mut sender, connection) = hyper::client::conn::handshake(stream).await?;
+    tokio::spawn(connection);
+
+    let request = hyper::Request::post(uri)
+        .header(hyper::header::HOST, "localhost")
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(hyper::Body::from(body))?;
+    let response = sender.send_request(request).await?;
+
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
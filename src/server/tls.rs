@@ -0,0 +1,119 @@
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::PKey;
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod, SslVerifyMode};
+use openssl::x509::{X509Name, X509};
+use std::fs;
+use std::path::Path;
+
+use crate::commands::{ClientTlsOptions, TlsOptions};
+
+/*
+ * TLS of the control server and of the cli
+ *
+ * - Start with --tls-cert and --tls-key serves https on its tcp addresses, the unix socket stays plain
+ * - --tls-client-ca turns on mutual tls: a client has to show a certificate signed by one of these cas
+ * - The cli trusts --ca-cert next to the roots of the system and shows --client-cert with --client-key
+ * - All of them are pem files, a certificate file can hold the whole chain
+ */
+
+impl TlsOptions {
+    // the options are checked before anything is bound
+    pub fn enabled(&self) -> anyhow::Result<bool> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(_), Some(_)) => Ok(true),
+            (None, None) if self.tls_client_ca.is_some() => {
+                Err(anyhow::anyhow!("--tls-client-ca needs --tls-cert and --tls-key"))
+            }
+            (None, None) => Ok(false),
+            _ => Err(anyhow::anyhow!("--tls-cert and --tls-key must be given together")),
+        }
+    }
+
+    // every address gets its own acceptor, they are built from the same files
+    pub fn acceptor(&self) -> anyhow::Result<SslAcceptorBuilder> {
+        let (cert, key) = match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => (cert, key),
+            _ => return Err(anyhow::anyhow!("--tls-cert and --tls-key must be given together")),
+        };
+        let fail = |path: &Path, e: openssl::error::ErrorStack| {
+            anyhow::anyhow!("Invalid tls file {}: {}", path.display(), e)
+        };
+
+        let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+        builder
+            .set_certificate_chain_file(cert)
+            .map_err(|e| fail(cert, e))?;
+        builder
+            .set_private_key_file(key, SslFiletype::PEM)
+            .map_err(|e| fail(key, e))?;
+        builder
+            .check_private_key()
+            .map_err(|e| anyhow::anyhow!("The key {} doesn't match the certificate: {}", key.display(), e))?;
+
+        if let Some(ca) = &self.tls_client_ca {
+            builder.set_ca_file(ca).map_err(|e| fail(ca, e))?;
+            builder.set_client_ca_list(X509Name::load_client_ca_file(ca).map_err(|e| fail(ca, e))?);
+            builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        }
+
+        Ok(builder)
+    }
+}
+
+impl ClientTlsOptions {
+    pub fn enabled(&self) -> bool {
+        self.ca_cert.is_some() || self.client_cert.is_some()
+    }
+
+    // the http client of the cli with the ca and the client certificate
+    pub fn client(&self) -> anyhow::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+
+        if let Some(ca) = &self.ca_cert {
+            let pem = read(ca)?;
+            let ca = reqwest::Certificate::from_pem(&pem)
+                .map_err(|e| anyhow::anyhow!("Invalid ca certificate {}: {}", ca.display(), e))?;
+            builder = builder.add_root_certificate(ca);
+        }
+
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => builder = builder.identity(Self::identity(cert, key)?),
+            (None, None) => (),
+            _ => return Err(anyhow::anyhow!("--client-cert and --client-key must be given together")),
+        }
+
+        Ok(builder.build()?)
+    }
+
+    // native-tls takes the client certificate as pkcs12, it is built from the pem files
+    // the first certificate of the file is the one of the client, the rest is its chain
+    fn identity(cert: &Path, key: &Path) -> anyhow::Result<reqwest::Identity> {
+        let fail = |path: &Path, e: openssl::error::ErrorStack| {
+            anyhow::anyhow!("Invalid tls file {}: {}", path.display(), e)
+        };
+
+        let mut chain = X509::stack_from_pem(&read(cert)?).map_err(|e| fail(cert, e))?.into_iter();
+        let leaf = chain
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("There is no certificate in {}", cert.display()))?;
+        let pkey = PKey::private_key_from_pem(&read(key)?).map_err(|e| fail(key, e))?;
+
+        let mut ca = openssl::stack::Stack::new()?;
+        for cert in chain {
+            ca.push(cert)?;
+        }
+        let pkcs12 = Pkcs12::builder()
+            .name("manager")
+            .pkey(&pkey)
+            .cert(&leaf)
+            .ca(ca)
+            .build2("")
+            .map_err(|e| fail(cert, e))?;
+
+        Ok(reqwest::Identity::from_pkcs12_der(&pkcs12.to_der()?, "")?)
+    }
+}
+
+fn read(path: &Path) -> anyhow::Result<Vec<u8>> {
+    fs::read(path).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))
+}
@@ -1,9 +1,35 @@
-use std::sync::mpsc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
 
 
 
-pub struct AppData<S, R> {
+pub struct AppData<S> {
     pub server_tx: mpsc::Sender<S>,
-    pub server_rx: mpsc::Receiver<R>,
-    pub download_path: String,
-}
\ No newline at end of file
+    // how long a request waits for the scheduler to take it and reply
+    pub reply_timeout: Duration,
+}
+
+// the scheduler didn't answer a request
+#[derive(Debug)]
+pub enum AskError {
+    // the scheduler stopped or dropped the reply channel
+    Unavailable,
+    Timeout,
+}
+
+impl<S> AppData<S> {
+    // send an event with its own reply channel to the scheduler and wait for the reply
+    pub async fn ask<T>(&self, event: impl FnOnce(oneshot::Sender<T>) -> S) -> Result<T, AskError> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        // a busy scheduler with a full channel counts against the same timeout as its reply
+        let asked = async {
+            self.server_tx.send(event(reply_tx)).await.map_err(|_| AskError::Unavailable)?;
+            reply_rx.await.map_err(|_| AskError::Unavailable)
+        };
+        match tokio::time::timeout(self.reply_timeout, asked).await {
+            Ok(reply) => reply,
+            Err(_) => Err(AskError::Timeout),
+        }
+    }
+}